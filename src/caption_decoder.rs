@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+use anyhow::Result;
+use crate::caption_event::{CaptionChannel, CaptionEvent, CaptionTime};
+use crate::cea608::Cea608Demuxer;
+use crate::cea708::{CaptionData, DtvccDemuxer};
+use crate::cea708_decoder::Cea708ServiceDecoder;
+
+// Turns cc_data, in presentation order, into captions: CEA-608 byte pairs go
+// to the CC1-CC4/T1-T4 decoders, DTVCC packets are split into services that
+// each get their own CEA-708 decoder
+pub struct CaptionDecoder {
+    cea608_demuxer: Cea608Demuxer,
+    dtvcc_demuxer: DtvccDemuxer,
+    cea708_decoders: BTreeMap<u8, Cea708ServiceDecoder>,
+}
+
+impl Default for CaptionDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CaptionDecoder {
+    pub fn new() -> Self {
+        Self {
+            cea608_demuxer: Cea608Demuxer::new(),
+            dtvcc_demuxer: DtvccDemuxer::new(),
+            cea708_decoders: BTreeMap::new(),
+        }
+    }
+
+    pub fn decode(&mut self, time: CaptionTime, caption_data: &[CaptionData]) -> Result<Vec<CaptionEvent>> {
+        let mut captions = self.cea608_demuxer.decode_caption_data(caption_data, time)?;
+        for block in self.dtvcc_demuxer.decode_caption_data(caption_data, time) {
+            let decoder = self.cea708_decoders
+                .entry(block.service_number)
+                .or_insert_with(|| Cea708ServiceDecoder::new(block.service_number));
+            captions.extend(decoder.decode_service_block(&block));
+        }
+        Ok(captions)
+    }
+
+    // Captions still on screen
+    pub fn flush(&mut self, time: CaptionTime) -> Vec<CaptionEvent> {
+        let mut captions = self.cea608_demuxer.flush(time);
+        for decoder in self.cea708_decoders.values_mut() {
+            captions.extend(decoder.flush(time));
+        }
+        captions
+    }
+
+    // Whether any of these channels (CEA-708 services as SERVICE1-63, or any
+    // channel when empty) carried caption data since the seen channels and
+    // services were last taken
+    pub fn has_caption_data(&self, channels: &[CaptionChannel]) -> bool {
+        let selected = |channel: &CaptionChannel| channels.is_empty() || channels.contains(channel);
+        self.cea608_demuxer.channels_seen().any(|channel| selected(&channel))
+            || self.dtvcc_demuxer.services_seen().any(|service| selected(&CaptionChannel::Service(service)))
+    }
+
+    // Caption channels that carried text since the last call
+    pub fn take_channels_seen(&mut self) -> Vec<CaptionChannel> {
+        self.cea608_demuxer.take_channels_seen()
+    }
+
+    // CEA-708 services that carried data since the last call
+    pub fn take_services_seen(&mut self) -> Vec<u8> {
+        self.dtvcc_demuxer.take_services_seen()
+    }
+
+    // Raw service block bytes received for a CEA-708 service since the last call
+    pub fn take_service_data(&mut self, service_number: u8) -> Vec<u8> {
+        self.dtvcc_demuxer.take_service_data(service_number)
+    }
+}
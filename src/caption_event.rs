@@ -0,0 +1,243 @@
+use std::fmt;
+use std::str::FromStr;
+use serde::{Serialize, Serializer};
+
+// CEA-608 caption (CC) and text (T) services. CC1/CC2/T1/T2 are carried in
+// field 1, CC3/CC4/T3/T4 in field 2. CEA-708 services are numbered 1-63.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CaptionChannel {
+    Cc1,
+    Cc2,
+    Cc3,
+    Cc4,
+    T1,
+    T2,
+    T3,
+    T4,
+    Service(u8),
+}
+
+impl CaptionChannel {
+    pub fn from_field(field: u8, data_channel: u8, text: bool) -> Self {
+        match (field, data_channel, text) {
+            (1, 1, false) => CaptionChannel::Cc1,
+            (1, 2, false) => CaptionChannel::Cc2,
+            (2, 1, false) => CaptionChannel::Cc3,
+            (2, 2, false) => CaptionChannel::Cc4,
+            (1, 1, true) => CaptionChannel::T1,
+            (1, 2, true) => CaptionChannel::T2,
+            (2, 1, true) => CaptionChannel::T3,
+            _ => CaptionChannel::T4,
+        }
+    }
+
+    pub fn is_text(&self) -> bool {
+        matches!(self, CaptionChannel::T1 | CaptionChannel::T2 | CaptionChannel::T3 | CaptionChannel::T4)
+    }
+}
+
+impl fmt::Display for CaptionChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CaptionChannel::Cc1 => "CC1",
+            CaptionChannel::Cc2 => "CC2",
+            CaptionChannel::Cc3 => "CC3",
+            CaptionChannel::Cc4 => "CC4",
+            CaptionChannel::T1 => "T1",
+            CaptionChannel::T2 => "T2",
+            CaptionChannel::T3 => "T3",
+            CaptionChannel::T4 => "T4",
+            CaptionChannel::Service(number) => return write!(f, "SERVICE{}", number),
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Serialize for CaptionChannel {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl FromStr for CaptionChannel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let upper = s.to_ascii_uppercase();
+        if let Some(number) = upper.strip_prefix("SERVICE") {
+            return match number.parse::<u8>() {
+                Ok(number @ 1..=63) => Ok(CaptionChannel::Service(number)),
+                _ => Err(format!("invalid CEA-708 service '{}' (expected SERVICE1-SERVICE63)", s)),
+            };
+        }
+        
+        match upper.as_str() {
+            "CC1" => Ok(CaptionChannel::Cc1),
+            "CC2" => Ok(CaptionChannel::Cc2),
+            "CC3" => Ok(CaptionChannel::Cc3),
+            "CC4" => Ok(CaptionChannel::Cc4),
+            "T1" => Ok(CaptionChannel::T1),
+            "T2" => Ok(CaptionChannel::T2),
+            "T3" => Ok(CaptionChannel::T3),
+            "T4" => Ok(CaptionChannel::T4),
+            _ => Err(format!("unknown caption channel '{}' (expected CC1-CC4, T1-T4 or SERVICE1-SERVICE63)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptionColor {
+    White,
+    Green,
+    Blue,
+    Cyan,
+    Red,
+    Yellow,
+    Magenta,
+    Black,
+    // CEA-708 colors outside the CEA-608 palette, scaled to 0-255
+    Rgb(u8, u8, u8),
+}
+
+impl CaptionColor {
+    // CEA-708 colors use two bits per component
+    pub fn from_rgb2(red: u8, green: u8, blue: u8) -> Self {
+        let on = |component: u8| component >= 2;
+        match (red, green, blue) {
+            (0, 0, 0) => CaptionColor::Black,
+            (r, g, b) if r == g && g == b && on(r) => CaptionColor::White,
+            (0, g, 0) if on(g) => CaptionColor::Green,
+            (0, 0, b) if on(b) => CaptionColor::Blue,
+            (0, g, b) if on(g) && g == b => CaptionColor::Cyan,
+            (r, 0, 0) if on(r) => CaptionColor::Red,
+            (r, g, 0) if on(r) && r == g => CaptionColor::Yellow,
+            (r, 0, b) if on(r) && r == b => CaptionColor::Magenta,
+            (r, g, b) => CaptionColor::Rgb(r * 85, g * 85, b * 85),
+        }
+    }
+}
+
+// Palette colors by name, other colors as #rrggbb
+impl Serialize for CaptionColor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = match self {
+            CaptionColor::White => "white",
+            CaptionColor::Green => "green",
+            CaptionColor::Blue => "blue",
+            CaptionColor::Cyan => "cyan",
+            CaptionColor::Red => "red",
+            CaptionColor::Yellow => "yellow",
+            CaptionColor::Magenta => "magenta",
+            CaptionColor::Black => "black",
+            CaptionColor::Rgb(r, g, b) => return serializer.collect_str(&format_args!("#{:02x}{:02x}{:02x}", r, g, b)),
+        };
+        serializer.serialize_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CaptionStyle {
+    pub color: CaptionColor,
+    pub italics: bool,
+    pub underline: bool,
+}
+
+impl Default for CaptionStyle {
+    fn default() -> Self {
+        Self {
+            color: CaptionColor::White,
+            italics: false,
+            underline: false,
+        }
+    }
+}
+
+// One character of a caption screen or window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptionCell {
+    pub ch: char,
+    pub style: CaptionStyle,
+}
+
+// A run of characters sharing the same style
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CaptionSpan {
+    pub text: String,
+    pub style: CaptionStyle,
+}
+
+// One row of a caption, positioned on the 15x32 caption grid
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CaptionLine {
+    pub row: usize,
+    pub column: usize,
+    pub spans: Vec<CaptionSpan>,
+}
+
+impl CaptionLine {
+    // The occupied part of a row of cells, split into spans of identical
+    // style; column is where the first cell sits. Transparent cells between
+    // characters render as spaces in the style of the character before them.
+    // None when the row is empty.
+    pub fn from_cells(row: usize, column: usize, cells: &[Option<CaptionCell>]) -> Option<CaptionLine> {
+        let first = cells.iter().position(|cell| cell.is_some())?;
+        let last = cells.iter().rposition(|cell| cell.is_some())?;
+
+        let mut spans: Vec<CaptionSpan> = Vec::new();
+        let mut style = CaptionStyle::default();
+
+        for cell in &cells[first..=last] {
+            let ch = match cell {
+                Some(cell) => {
+                    style = cell.style;
+                    cell.ch
+                }
+                None => ' ',
+            };
+
+            match spans.last_mut() {
+                Some(span) if span.style == style => span.text.push(ch),
+                _ => spans.push(CaptionSpan {
+                    text: ch.to_string(),
+                    style,
+                }),
+            }
+        }
+
+        Some(CaptionLine {
+            row,
+            column: column + first,
+            spans,
+        })
+    }
+
+    pub fn text(&self) -> String {
+        self.spans.iter().map(|span| span.text.as_str()).collect()
+    }
+}
+
+// A point on the caption timeline: the 90 kHz presentation timestamp
+// (unwrapped, so it keeps counting past the 33-bit limit) and the time in
+// seconds relative to the start of the playlist.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct CaptionTime {
+    pub pts: u64,
+    pub seconds: f64,
+}
+
+impl CaptionTime {
+    pub fn max(self, other: CaptionTime) -> CaptionTime {
+        if other.pts > self.pts { other } else { self }
+    }
+}
+
+// A decoded caption as it was presented on screen, from the moment it
+// became visible until it was erased, replaced or scrolled away.
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptionEvent {
+    pub channel: CaptionChannel,
+    pub text: String,
+    pub start: CaptionTime,
+    pub end: CaptionTime,
+    pub lines: Vec<CaptionLine>,
+}
@@ -0,0 +1,517 @@
+use anyhow::Result;
+use crate::caption_decoder::CaptionDecoder;
+use crate::caption_event::{CaptionChannel, CaptionEvent, CaptionTime};
+use crate::cc_reorder::{CcReorderBuffer, DEFAULT_REORDER_DEPTH};
+use crate::cea708::{CaptionData, Cea708Parser};
+use crate::fmp4::{self, Fmp4Track};
+use crate::nal::{self, CaptionPayload, VideoCodec};
+use crate::pes::{PesAssembler, PesPacket, PTS_WRAP};
+use crate::ts_demux::{TsDemuxer, TS_PACKET_SIZE, TS_SYNC_BYTE};
+
+const PTS_CLOCK_RATE: f64 = 90000.0;
+
+// Extracts captions from a video stream. Each stage hands its own type to
+// the next one:
+//
+//   TS packets → TsPayload (TsDemuxer) → PesPacket (PesAssembler)
+//   → NAL units (nal::annex_b_units) → CaptionPayload (nal::caption_payloads)
+//   → cc_data (Cea708Parser) → presentation order (CcReorderBuffer)
+//   → CaptionEvent (CaptionDecoder)
+//
+// Fragmented MP4 segments enter at the NAL unit stage. Decoder state carries
+// over from one segment to the next, so one extractor serves a whole input.
+pub struct CaptionExtractor {
+    demuxer: TsDemuxer,
+    partial_packet: Vec<u8>,
+    pes_assembler: PesAssembler,
+    codec: Option<VideoCodec>,
+    current_time: CaptionTime,
+    last_pts: Option<u64>,
+    pts_wrap_offset: u64,
+    segment_offset: f64,
+    segment_origin_pts: Option<u64>,
+    cea708_parser: Cea708Parser,
+    reorder_buffer: CcReorderBuffer,
+    decoder: CaptionDecoder,
+    presence_only: bool,
+    presence_channels: Vec<CaptionChannel>,
+    record_cc_data: bool,
+    recorded_cc_data: Vec<(CaptionTime, Vec<CaptionData>)>,
+}
+
+impl Default for CaptionExtractor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CaptionExtractor {
+    pub fn new() -> Self {
+        Self {
+            demuxer: TsDemuxer::new(),
+            partial_packet: Vec::new(),
+            pes_assembler: PesAssembler::new(),
+            codec: None,
+            current_time: CaptionTime::default(),
+            last_pts: None,
+            pts_wrap_offset: 0,
+            segment_offset: 0.0,
+            segment_origin_pts: None,
+            cea708_parser: Cea708Parser::new(),
+            reorder_buffer: CcReorderBuffer::new(DEFAULT_REORDER_DEPTH),
+            decoder: CaptionDecoder::new(),
+            presence_only: false,
+            presence_channels: Vec::new(),
+            record_cc_data: false,
+            recorded_cc_data: Vec::new(),
+        }
+    }
+
+    // Start of the next segment on the playlist timeline. Caption times are
+    // measured from the first PTS of the segment plus this offset, so they
+    // stay continuous across the whole playlist.
+    pub fn set_segment_offset(&mut self, offset: f64) {
+        self.segment_offset = offset;
+        self.segment_origin_pts = None;
+    }
+
+    // Number of access units whose cc_data is held back to restore
+    // presentation order (0 decodes in decode order)
+    pub fn set_reorder_depth(&mut self, depth: usize) {
+        self.reorder_buffer.set_depth(depth);
+    }
+
+    // The next segment follows an EXT-X-DISCONTINUITY: timestamps restart, so
+    // held back caption data is released, captions still on screen end here
+    // (their end could not be compared with a start before the restart) and
+    // PTS wraparound tracking resets
+    pub fn mark_discontinuity(&mut self) -> Result<Vec<CaptionEvent>> {
+        self.reset_pts_unwrapping();
+        self.flush()
+    }
+
+    // Stops decoding a segment as soon as it has carried caption data, which
+    // is enough to tell whether it carries captions at all
+    pub fn set_presence_only(&mut self, presence_only: bool) {
+        self.presence_only = presence_only;
+    }
+
+    // Channels whose caption data counts for presence_only; empty counts
+    // every channel
+    pub fn set_presence_channels(&mut self, channels: Vec<CaptionChannel>) {
+        self.presence_channels = channels;
+    }
+
+    // Keeps the cc_data of every frame for take_cc_data
+    pub fn set_record_cc_data(&mut self, record: bool) {
+        self.record_cc_data = record;
+    }
+
+    pub fn parse_ts_file(&mut self, data: &[u8]) -> Result<Vec<CaptionEvent>> {
+        let mut captions = Vec::new();
+        
+        for packet in data.chunks_exact(TS_PACKET_SIZE) {
+            captions.extend(self.process_ts_packet(packet)?);
+            
+            // Early exit in presence-only mode once caption data was found
+            if self.can_stop() {
+                break;
+            }
+        }
+        
+        captions.extend(self.end_ts_data()?);
+        Ok(captions)
+    }
+
+    // Whether the rest of the segment can be skipped because it has carried
+    // caption data for a selected channel. The data is counted until the
+    // seen channels and services are taken, at the end of each segment.
+    pub fn can_stop(&self) -> bool {
+        self.presence_only && self.decoder.has_caption_data(&self.presence_channels)
+    }
+
+    // Streaming input: takes transport stream bytes as they arrive, in chunks
+    // of any size. Packets split across chunks are completed by the next call.
+    pub fn push_ts_data(&mut self, data: &[u8]) -> Result<Vec<CaptionEvent>> {
+        let mut captions = Vec::new();
+        self.partial_packet.extend_from_slice(data);
+        
+        let mut offset = 0;
+        while offset + TS_PACKET_SIZE <= self.partial_packet.len() {
+            // Resynchronize on the next sync byte after corrupt or lost data
+            if self.partial_packet[offset] != TS_SYNC_BYTE {
+                offset += 1;
+                continue;
+            }
+            
+            let packet: [u8; TS_PACKET_SIZE] = self.partial_packet[offset..offset + TS_PACKET_SIZE].try_into()?;
+            captions.extend(self.process_ts_packet(&packet)?);
+            offset += TS_PACKET_SIZE;
+        }
+        
+        self.partial_packet.drain(..offset);
+        Ok(captions)
+    }
+
+    // End of a segment or stream: decodes the last PES packet and releases
+    // the caption data held back for reordering
+    pub fn end_ts_data(&mut self) -> Result<Vec<CaptionEvent>> {
+        self.partial_packet.clear();
+        
+        let mut captions = Vec::new();
+        for pes_packet in self.pes_assembler.flush() {
+            captions.extend(self.process_pes_packet(pes_packet)?);
+        }
+        captions.extend(self.flush_reorder_buffer()?);
+        Ok(captions)
+    }
+
+    // Drops transport stream data that was received but not decoded yet, as
+    // when a download fails and the rest of the segment never arrives. The
+    // next segment then starts on a packet and PES packet boundary.
+    pub fn discard_ts_data(&mut self) {
+        self.partial_packet.clear();
+        self.pes_assembler = PesAssembler::new();
+    }
+
+    fn process_ts_packet(&mut self, packet: &[u8]) -> Result<Vec<CaptionEvent>> {
+        let mut captions = Vec::new();
+        
+        let Some(payload) = self.demuxer.push_packet(packet) else {
+            return Ok(captions);
+        };
+        self.codec = self.demuxer.codec();
+        
+        // The discontinuity indicator restarts the timestamps of the stream.
+        // Whatever arrived before it is timed on the old timeline, so it is
+        // decoded before wraparound tracking starts over. Captions on screen
+        // are left alone; only an EXT-X-DISCONTINUITY ends them.
+        if payload.discontinuity {
+            for pes_packet in self.pes_assembler.flush() {
+                captions.extend(self.process_pes_packet(pes_packet)?);
+            }
+            captions.extend(self.flush_reorder_buffer()?);
+            self.reset_pts_unwrapping();
+        }
+        
+        for pes_packet in self.pes_assembler.push(payload.pid, payload.unit_start, payload.data) {
+            captions.extend(self.process_pes_packet(pes_packet)?);
+        }
+        
+        Ok(captions)
+    }
+
+    // Decodes the access units of a complete PES packet at its PTS
+    fn process_pes_packet(&mut self, pes_packet: PesPacket) -> Result<Vec<CaptionEvent>> {
+        let Some(codec) = self.codec else {
+            return Ok(Vec::new());
+        };
+        
+        if let Some(pts) = pes_packet.header.pts {
+            let pts = self.unwrap_pts(pts);
+            self.set_pts(pts);
+        }
+        
+        let mut captions = Vec::new();
+        for unit in nal::annex_b_units(&pes_packet.data) {
+            for caption_data in unit_caption_data(&self.cea708_parser, codec, unit)? {
+                captions.extend(self.decode_caption_data(caption_data)?);
+            }
+        }
+        Ok(captions)
+    }
+
+    // Fragmented MP4 segments carry length-prefixed NAL units instead of a TS
+    // bytestream; each one goes through the same SEI path
+    pub fn parse_fmp4_segment(&mut self, track: &Fmp4Track, data: &[u8]) -> Result<Vec<CaptionEvent>> {
+        let mut captions = Vec::new();
+        self.codec = Some(track.codec);
+        
+        for sample in fmp4::parse_media_segment(track, data)? {
+            self.set_pts(sample.pts);
+            
+            for unit in fmp4::nal_units(sample.data, track.nal_length_size) {
+                for caption_data in unit_caption_data(&self.cea708_parser, track.codec, unit)? {
+                    captions.extend(self.decode_caption_data(caption_data)?);
+                }
+            }
+            
+            // Early exit in presence-only mode once caption data was found
+            if self.can_stop() {
+                break;
+            }
+        }
+        
+        captions.extend(self.flush_reorder_buffer()?);
+        Ok(captions)
+    }
+
+    // Emit whatever caption is still on screen at the end of the stream
+    pub fn flush(&mut self) -> Result<Vec<CaptionEvent>> {
+        let mut captions = self.flush_reorder_buffer()?;
+        captions.extend(self.decoder.flush(self.current_time));
+        Ok(captions)
+    }
+
+    // Caption channels that carried text since the last call
+    pub fn take_channels_seen(&mut self) -> Vec<CaptionChannel> {
+        self.decoder.take_channels_seen()
+    }
+
+    // CEA-708 services that carried data since the last call
+    pub fn take_services_seen(&mut self) -> Vec<u8> {
+        self.decoder.take_services_seen()
+    }
+
+    // Raw service block bytes received for a CEA-708 service since the last call
+    pub fn take_service_data(&mut self, service_number: u8) -> Vec<u8> {
+        self.decoder.take_service_data(service_number)
+    }
+
+    // cc_data of each frame in presentation order since the last call, when
+    // recording is enabled
+    pub fn take_cc_data(&mut self) -> Vec<(CaptionTime, Vec<CaptionData>)> {
+        std::mem::take(&mut self.recorded_cc_data)
+    }
+
+    // Extends a 33-bit PTS past its wraparound point. A PTS far below the last
+    // one means the counter wrapped; one far above it (B-frames around the
+    // wrap) still belongs before it.
+    fn unwrap_pts(&mut self, pts: u64) -> u64 {
+        let mut unwrapped = pts + self.pts_wrap_offset;
+        
+        if let Some(last_pts) = self.last_pts {
+            if unwrapped + PTS_WRAP / 2 < last_pts {
+                self.pts_wrap_offset += PTS_WRAP;
+                unwrapped += PTS_WRAP;
+            } else if unwrapped > last_pts + PTS_WRAP / 2 && self.pts_wrap_offset >= PTS_WRAP {
+                return unwrapped - PTS_WRAP;
+            }
+        }
+        
+        self.last_pts = Some(unwrapped);
+        unwrapped
+    }
+
+    fn reset_pts_unwrapping(&mut self) {
+        self.last_pts = None;
+        self.pts_wrap_offset = 0;
+    }
+
+    fn set_pts(&mut self, pts: u64) {
+        let origin = *self.segment_origin_pts.get_or_insert(pts);
+        self.current_time = CaptionTime {
+            pts,
+            seconds: self.segment_offset + (pts as i64 - origin as i64) as f64 / PTS_CLOCK_RATE,
+        };
+    }
+
+    // Queues the cc_data of the current access unit and decodes whatever the
+    // reorder buffer releases in presentation order
+    fn decode_caption_data(&mut self, caption_data: Vec<CaptionData>) -> Result<Vec<CaptionEvent>> {
+        let mut captions = Vec::new();
+        for (time, caption_data) in self.reorder_buffer.push(self.current_time, caption_data) {
+            captions.extend(self.decode_ordered_caption_data(time, &caption_data)?);
+        }
+        Ok(captions)
+    }
+
+    fn flush_reorder_buffer(&mut self) -> Result<Vec<CaptionEvent>> {
+        let mut captions = Vec::new();
+        for (time, caption_data) in self.reorder_buffer.flush() {
+            captions.extend(self.decode_ordered_caption_data(time, &caption_data)?);
+        }
+        Ok(captions)
+    }
+
+    fn decode_ordered_caption_data(&mut self, time: CaptionTime, caption_data: &[CaptionData]) -> Result<Vec<CaptionEvent>> {
+        if self.record_cc_data {
+            self.recorded_cc_data.push((time, caption_data.to_vec()));
+        }
+        self.decoder.decode(time, caption_data)
+    }
+}
+
+// The cc_data of each caption payload in a NAL unit
+fn unit_caption_data(parser: &Cea708Parser, codec: VideoCodec, unit: &[u8]) -> Result<Vec<Vec<CaptionData>>> {
+    nal::caption_payloads(codec, unit)
+        .into_iter()
+        .map(|payload| match payload {
+            CaptionPayload::ItuT35(data) => parser.parse_user_data(&data),
+            CaptionPayload::Mpeg2UserData(data) => parser.parse_mpeg2_user_data(data),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::ts_demux::tests::{pat_packet, payload_packets, pmt_packet};
+
+    const PMT_PID: u16 = 0x1000;
+    const VIDEO_PID: u16 = 0x100;
+    pub(crate) const FIRST_PTS: u64 = 900_000;
+    pub(crate) const FRAME_TICKS: u64 = 3003;
+
+    // A PES packet of unbounded length with only a PTS
+    fn pes(pts: u64, data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x00, 0x00, 0x01, 0xE0, 0x00, 0x00, 0x80, 0x80, 0x05];
+        packet.extend([
+            0x21 | ((pts >> 29) & 0x0E) as u8,
+            (pts >> 22) as u8,
+            0x01 | ((pts >> 14) & 0xFE) as u8,
+            (pts >> 7) as u8,
+            0x01 | ((pts << 1) & 0xFE) as u8,
+        ]);
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    // An H.264 access unit whose SEI carries one field 1 byte pair
+    fn caption_frame(data1: u8, data2: u8) -> Vec<u8> {
+        let t35 = [0xB5, 0x00, 0x31, b'G', b'A', b'9', b'4', 0x03, 0x41, 0xFF, 0xFC, data1, data2, 0xFF];
+        let mut frame = vec![0x00, 0x00, 0x00, 0x01, 0x09, 0xF0];
+        frame.extend([0x00, 0x00, 0x01, 0x06, 0x04, t35.len() as u8]);
+        frame.extend(t35);
+        frame.extend([0x80, 0x00, 0x00, 0x01, 0x65, 0x88, 0x84]);
+        frame
+    }
+
+    // The single packet of a caption frame, whose adaptation field flags are
+    // at byte 5
+    pub(crate) fn frame_packet(pts: u64, data1: u8, data2: u8) -> Vec<u8> {
+        payload_packets(VIDEO_PID, &pes(pts, &caption_frame(data1, data2)))
+    }
+
+    // A transport stream with one frame per byte pair, 29.97 frames apart
+    pub(crate) fn caption_stream(stream_type: u8, pairs: &[(u8, u8)]) -> Vec<u8> {
+        let mut stream = pat_packet(&[(1, PMT_PID)]);
+        stream.extend(pmt_packet(PMT_PID, 0, &[(stream_type, VIDEO_PID)]));
+        for (frame, &(data1, data2)) in pairs.iter().enumerate() {
+            stream.extend(frame_packet(FIRST_PTS + frame as u64 * FRAME_TICKS, data1, data2));
+        }
+        stream
+    }
+
+    // A pop-on "HI" shown at frame 3 and erased at frame 10
+    pub(crate) fn pop_on_pairs() -> Vec<(u8, u8)> {
+        let mut pairs = vec![(0x14, 0x20), (0x14, 0x20), (b'H', b'I'), (0x14, 0x2F), (0x14, 0x2F)];
+        pairs.extend([(0x80, 0x80); 5]);
+        pairs.extend([(0x14, 0x2C), (0x14, 0x2C)]);
+        pairs
+    }
+
+    fn time(frame: u64) -> CaptionTime {
+        CaptionTime {
+            pts: FIRST_PTS + frame * FRAME_TICKS,
+            seconds: (frame * FRAME_TICKS) as f64 / PTS_CLOCK_RATE,
+        }
+    }
+
+    #[test]
+    fn transport_stream_yields_timed_captions() {
+        let mut extractor = CaptionExtractor::new();
+        
+        let captions = extractor.parse_ts_file(&caption_stream(0x1B, &pop_on_pairs())).unwrap();
+        
+        assert_eq!(captions.len(), 1);
+        let caption = &captions[0];
+        assert_eq!(caption.channel, CaptionChannel::Cc1);
+        assert_eq!(caption.text, "HI");
+        assert_eq!(caption.start, time(3));
+        assert_eq!(caption.end, time(10));
+        assert_eq!(extractor.take_channels_seen(), [CaptionChannel::Cc1]);
+    }
+
+    // Stream type 0xDB is H.264 with SAMPLE-AES encrypted slices. Nothing is
+    // decrypted: the captions come from the SEI NAL units, which stay clear.
+    #[test]
+    fn sample_aes_stream_yields_captions_from_clear_sei() {
+        let mut extractor = CaptionExtractor::new();
+        
+        let captions = extractor.parse_ts_file(&caption_stream(0xDB, &pop_on_pairs())).unwrap();
+        
+        assert_eq!(captions.len(), 1);
+        assert_eq!(captions[0].text, "HI");
+        assert_eq!(captions[0].start, time(3));
+    }
+
+    #[test]
+    fn streamed_chunks_match_whole_file() {
+        let stream = caption_stream(0x1B, &pop_on_pairs());
+        let mut extractor = CaptionExtractor::new();
+        
+        let mut captions = Vec::new();
+        for chunk in stream.chunks(100) {
+            captions.extend(extractor.push_ts_data(chunk).unwrap());
+        }
+        captions.extend(extractor.end_ts_data().unwrap());
+        
+        let whole = CaptionExtractor::new().parse_ts_file(&stream).unwrap();
+        let summary = |captions: &[CaptionEvent]| -> Vec<_> {
+            captions.iter().map(|caption| (caption.text.clone(), caption.start, caption.end)).collect()
+        };
+        assert_eq!(captions.len(), 1);
+        assert_eq!(summary(&captions), summary(&whole));
+    }
+
+    #[test]
+    fn pts_unwraps_past_33_bits() {
+        let mut extractor = CaptionExtractor::new();
+        assert_eq!(extractor.unwrap_pts(PTS_WRAP - FRAME_TICKS), PTS_WRAP - FRAME_TICKS);
+        assert_eq!(extractor.unwrap_pts(1000), PTS_WRAP + 1000);
+        
+        // A B-frame from before the wrap stays before it
+        assert_eq!(extractor.unwrap_pts(PTS_WRAP - 1000), PTS_WRAP - 1000);
+        assert_eq!(extractor.unwrap_pts(1000 + FRAME_TICKS), PTS_WRAP + 1000 + FRAME_TICKS);
+        
+        // The offset keeps growing with every wrap
+        assert_eq!(extractor.unwrap_pts(PTS_WRAP / 2), PTS_WRAP + PTS_WRAP / 2);
+        assert_eq!(extractor.unwrap_pts(PTS_WRAP - FRAME_TICKS), 2 * PTS_WRAP - FRAME_TICKS);
+        assert_eq!(extractor.unwrap_pts(0), 2 * PTS_WRAP);
+        
+        // After a reset a low PTS is taken as it is
+        extractor.reset_pts_unwrapping();
+        assert_eq!(extractor.unwrap_pts(1000), 1000);
+    }
+
+    #[test]
+    fn times_are_measured_from_segment_start() {
+        let mut extractor = CaptionExtractor::new();
+        extractor.set_segment_offset(10.0);
+        extractor.set_pts(FIRST_PTS);
+        assert_eq!(extractor.current_time.seconds, 10.0);
+        extractor.set_pts(FIRST_PTS + 90_000);
+        assert_eq!(extractor.current_time.seconds, 11.0);
+        
+        // Each segment is measured from its own first PTS, and frames
+        // presented before it come out earlier
+        extractor.set_segment_offset(12.0);
+        extractor.set_pts(FIRST_PTS + 900_000);
+        assert_eq!(extractor.current_time.seconds, 12.0);
+        extractor.set_pts(FIRST_PTS + 900_000 - 4500);
+        assert_eq!(extractor.current_time.seconds, 11.95);
+        assert_eq!(extractor.current_time.pts, FIRST_PTS + 900_000 - 4500);
+    }
+
+    #[test]
+    fn discontinuity_indicator_keeps_caption_on_screen() {
+        let mut extractor = CaptionExtractor::new();
+        let mut stream = caption_stream(0x1B, &pop_on_pairs()[..5]);
+        
+        // Timestamps jump ahead at the indicator
+        let restart_pts = FIRST_PTS + 3_000_000;
+        let mut restart = frame_packet(restart_pts, 0x80, 0x80);
+        restart[5] |= 0x80;
+        stream.extend(restart);
+        stream.extend(frame_packet(restart_pts + FRAME_TICKS, 0x14, 0x2C));
+        stream.extend(frame_packet(restart_pts + 2 * FRAME_TICKS, 0x14, 0x2C));
+        
+        let captions = extractor.parse_ts_file(&stream).unwrap();
+        
+        assert_eq!(captions.len(), 1);
+        assert_eq!(captions[0].text, "HI");
+        assert_eq!(captions[0].start, time(3));
+        assert_eq!(captions[0].end.pts, restart_pts + FRAME_TICKS);
+    }
+}
@@ -0,0 +1,95 @@
+use std::path::Path;
+use anyhow::Result;
+use clap::ValueEnum;
+use hlscaptionfinder::caption_event::{CaptionChannel, CaptionEvent, CaptionTime};
+use hlscaptionfinder::cea708::CaptionData;
+use hlscaptionfinder::mcc_writer::MccWriter;
+use hlscaptionfinder::scc_writer::SccWriter;
+use hlscaptionfinder::subtitle_writer::{SubtitleFormat, SubtitleWriter};
+use hlscaptionfinder::ttml_writer::TtmlWriter;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Srt,
+    Vtt,
+    Scc,
+    Mcc,
+    Ttml,
+}
+
+impl OutputFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "srt" => Some(OutputFormat::Srt),
+            "vtt" => Some(OutputFormat::Vtt),
+            "scc" => Some(OutputFormat::Scc),
+            "mcc" => Some(OutputFormat::Mcc),
+            "ttml" => Some(OutputFormat::Ttml),
+            _ => None,
+        }
+    }
+}
+
+// Caption file written next to the console output. Subtitle formats take the
+// decoded captions, while caption formats take the raw cc_data of each frame.
+pub enum CaptionOutput {
+    Subtitles(SubtitleWriter),
+    Scc(SccWriter),
+    Mcc(MccWriter),
+    Ttml(TtmlWriter),
+}
+
+impl CaptionOutput {
+    pub fn create(path: &Path, format: OutputFormat, channels: &[CaptionChannel]) -> Result<Self> {
+        match format {
+            OutputFormat::Srt => Ok(CaptionOutput::Subtitles(SubtitleWriter::create(path, SubtitleFormat::Srt)?)),
+            OutputFormat::Vtt => Ok(CaptionOutput::Subtitles(SubtitleWriter::create(path, SubtitleFormat::Vtt)?)),
+            OutputFormat::Scc => {
+                // CC1 unless a CEA-608 channel was selected
+                let channel = channels
+                    .iter()
+                    .copied()
+                    .find(|channel| !matches!(channel, CaptionChannel::Service(_)))
+                    .or(channels.first().copied())
+                    .unwrap_or(CaptionChannel::Cc1);
+                Ok(CaptionOutput::Scc(SccWriter::create(path, channel)?))
+            }
+            OutputFormat::Mcc => Ok(CaptionOutput::Mcc(MccWriter::create(path)?)),
+            OutputFormat::Ttml => Ok(CaptionOutput::Ttml(TtmlWriter::create(path)?)),
+        }
+    }
+
+    pub fn needs_cc_data(&self) -> bool {
+        matches!(self, CaptionOutput::Scc(_) | CaptionOutput::Mcc(_))
+    }
+
+    // Documents that are only written once the playlist has ended
+    pub fn needs_playlist_end(&self) -> bool {
+        matches!(self, CaptionOutput::Ttml(_))
+    }
+
+    pub fn write_caption(&mut self, caption: &CaptionEvent) -> Result<()> {
+        match self {
+            CaptionOutput::Subtitles(writer) => writer.write_caption(caption),
+            CaptionOutput::Ttml(writer) => writer.write_caption(caption),
+            CaptionOutput::Scc(_) | CaptionOutput::Mcc(_) => Ok(()),
+        }
+    }
+
+    pub fn write_cc_data(&mut self, frames: Vec<(CaptionTime, Vec<CaptionData>)>) -> Result<()> {
+        match self {
+            CaptionOutput::Subtitles(_) | CaptionOutput::Ttml(_) => Ok(()),
+            CaptionOutput::Scc(writer) => writer.write_cc_data(frames),
+            CaptionOutput::Mcc(writer) => writer.write_cc_data(frames),
+        }
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        match self {
+            CaptionOutput::Subtitles(_) => Ok(()),
+            CaptionOutput::Scc(writer) => writer.finish(),
+            CaptionOutput::Mcc(writer) => writer.finish(),
+            CaptionOutput::Ttml(writer) => writer.finish(),
+        }
+    }
+}
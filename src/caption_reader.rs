@@ -0,0 +1,600 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::time::Duration;
+use anyhow::Result;
+use log::{debug, info};
+use reqwest::Client;
+use tokio::io::AsyncRead;
+use tokio::net::UdpSocket;
+use tokio::time::sleep;
+use crate::caption_event::{CaptionChannel, CaptionEvent, CaptionTime};
+use crate::caption_extractor::CaptionExtractor;
+use crate::cc_reorder::DEFAULT_REORDER_DEPTH;
+use crate::cea708::CaptionData;
+use crate::encryption;
+use crate::fmp4;
+use crate::hls::{HlsParser, PlaylistType, Segment, SegmentBody};
+use crate::input::Input;
+use crate::udp_input;
+
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
+// How a reader decodes its input
+#[derive(Debug, Clone)]
+pub struct ReaderOptions {
+    // Number of frames of caption data held back to restore presentation
+    // order with B-frames (0 disables reordering)
+    pub reorder_depth: usize,
+    // Stop reading each segment once it has carried caption data, which
+    // only tells which segments carry captions
+    pub presence_only: bool,
+    // Channels whose caption data counts for presence_only; empty counts
+    // every channel
+    pub presence_channels: Vec<CaptionChannel>,
+    // Hand out the cc_data of every frame along with the captions
+    pub record_cc_data: bool,
+}
+
+impl Default for ReaderOptions {
+    fn default() -> Self {
+        Self {
+            reorder_depth: DEFAULT_REORDER_DEPTH,
+            presence_only: false,
+            presence_channels: Vec::new(),
+            record_cc_data: false,
+        }
+    }
+}
+
+// The shape of an input, which decides the events a reader produces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    // A VOD playlist or local segment files: SegmentsFound, SegmentStarted
+    // and Segment for each segment, then Finished
+    Segments,
+    // A live playlist: SegmentStarted and Segment for each new segment, and
+    // PollFinished after each reload. It never finishes.
+    Live { target_duration: u32 },
+    // A transport stream without segments, from UDP or a byte source:
+    // Captions as they are decoded, and Finished once a byte source ends
+    Stream,
+}
+
+// Captions decoded outside of a segment
+#[derive(Debug, Default)]
+pub struct DecodedCaptions {
+    pub captions: Vec<CaptionEvent>,
+    // cc_data of each frame in presentation order, with record_cc_data
+    pub cc_data: Vec<(CaptionTime, Vec<CaptionData>)>,
+}
+
+// Everything found in one segment
+#[derive(Debug)]
+pub struct SegmentCaptions {
+    pub segment: Segment,
+    // Captions that ended in this segment
+    pub captions: Vec<CaptionEvent>,
+    // Caption channels that carried text
+    pub channels: Vec<CaptionChannel>,
+    // Raw service block bytes of the CEA-708 services that carried data
+    pub services: BTreeMap<u8, Vec<u8>>,
+    // cc_data of each frame in presentation order, with record_cc_data
+    pub cc_data: Vec<(CaptionTime, Vec<CaptionData>)>,
+    // Set when the segment could not be downloaded or decoded; it has no
+    // captions then
+    pub error: Option<anyhow::Error>,
+}
+
+pub enum ReaderEvent {
+    // Number of segments of a VOD playlist or local input
+    SegmentsFound(usize),
+    // A segment is being read; segments are numbered from 1 over the input
+    SegmentStarted { number: usize, uri: String },
+    Segment(Box<SegmentCaptions>),
+    // Captions decoded from a stream
+    Captions(DecodedCaptions),
+    // Reloading a live playlist or decoding stream data failed; reading
+    // goes on
+    Error(anyhow::Error),
+    // A live playlist reload and its new segments are done. The next reload
+    // follows one target duration later.
+    PollFinished,
+    // End of the input, with the captions still on screen
+    Finished(DecodedCaptions),
+}
+
+// A caption with the segment it ended in
+#[derive(Debug, Clone)]
+pub struct Caption {
+    pub event: CaptionEvent,
+    // None for captions from a stream and captions still on screen at the end
+    pub segment: Option<Segment>,
+}
+
+struct LivePlaylist {
+    url: String,
+    poll_interval: Duration,
+    seen_segments: HashSet<String>,
+    polled: bool,
+    reloaded: bool,
+}
+
+enum Source {
+    // A fixed list of segments
+    Segments,
+    Live(LivePlaylist),
+    Udp(UdpSocket, Vec<u8>),
+    Bytes(SegmentBody),
+}
+
+// Reads captions from an HLS playlist, local segments, a UDP stream or any
+// byte source. Caption decoder state carries across segments, so captions
+// that span segment boundaries come out whole.
+pub struct CaptionReader {
+    hls_parser: HlsParser,
+    extractor: CaptionExtractor,
+    source: Source,
+    segments: VecDeque<Segment>,
+    // Announced by SegmentStarted, read by the next call
+    started_segment: Option<Segment>,
+    segment_number: usize,
+    events: VecDeque<ReaderEvent>,
+    captions: VecDeque<Caption>,
+    finished: bool,
+}
+
+impl CaptionReader {
+    // Opens an HLS playlist URL, local playlist, .ts file, directory of .ts
+    // segments, udp://@group:port stream, or "-" for stdin
+    pub async fn open(input: &str, options: ReaderOptions) -> Result<Self> {
+        Self::open_input(Client::new(), Input::parse(input)?, options).await
+    }
+    
+    pub async fn open_input(client: Client, input: Input, options: ReaderOptions) -> Result<Self> {
+        let hls_parser = HlsParser::new(client);
+        let mut segments = Vec::new();
+        let source = match input {
+            Input::Playlist(url) => match hls_parser.determine_playlist_type(&url).await? {
+                PlaylistType::Live { target_duration } => {
+                    info!("Detected live playlist with target duration: {}s", target_duration);
+                    Source::Live(LivePlaylist {
+                        url,
+                        poll_interval: Duration::from_secs(target_duration as u64),
+                        seen_segments: HashSet::new(),
+                        polled: false,
+                        reloaded: false,
+                    })
+                }
+                PlaylistType::Vod => {
+                    info!("Detected VOD playlist");
+                    segments = hls_parser.get_lowest_bitrate_segments(&url).await?;
+                    Source::Segments
+                }
+            },
+            Input::Segments(local_segments) => {
+                info!("Processing {} local segments", local_segments.len());
+                segments = local_segments;
+                Source::Segments
+            }
+            Input::Udp(udp_source) => {
+                let socket = udp_source.bind().await?;
+                info!("Listening for MPEG-TS on {}", udp_source);
+                Source::Udp(socket, vec![0u8; MAX_DATAGRAM_SIZE])
+            }
+        };
+        
+        let mut reader = Self::new(hls_parser, source, &options);
+        if matches!(reader.source, Source::Segments) {
+            info!("Found {} segments to process", segments.len());
+            reader.events.push_back(ReaderEvent::SegmentsFound(segments.len()));
+            reader.segments.extend(segments);
+        }
+        Ok(reader)
+    }
+
+    // Reads a transport stream from any byte source, such as a socket or a
+    // pipe, until it ends
+    pub fn from_reader(reader: impl AsyncRead + Unpin + Send + 'static, options: ReaderOptions) -> Self {
+        Self::new(HlsParser::new(Client::new()), Source::Bytes(SegmentBody::Reader(Box::new(reader))), &options)
+    }
+
+    fn new(hls_parser: HlsParser, source: Source, options: &ReaderOptions) -> Self {
+        let mut extractor = CaptionExtractor::new();
+        extractor.set_reorder_depth(options.reorder_depth);
+        extractor.set_presence_only(options.presence_only);
+        extractor.set_presence_channels(options.presence_channels.clone());
+        extractor.set_record_cc_data(options.record_cc_data);
+        
+        Self {
+            hls_parser,
+            extractor,
+            source,
+            segments: VecDeque::new(),
+            started_segment: None,
+            segment_number: 0,
+            events: VecDeque::new(),
+            captions: VecDeque::new(),
+            finished: false,
+        }
+    }
+
+    pub fn kind(&self) -> SourceKind {
+        match &self.source {
+            Source::Segments => SourceKind::Segments,
+            Source::Live(playlist) => SourceKind::Live { target_duration: playlist.poll_interval.as_secs() as u32 },
+            Source::Udp(..) | Source::Bytes(_) => SourceKind::Stream,
+        }
+    }
+
+    // The next event, or None once the input has been read completely.
+    // Waiting for stream data can be cancelled without losing any, so a
+    // stream can be raced against a shutdown signal and then ended with
+    // finish().
+    pub async fn next_event(&mut self) -> Result<Option<ReaderEvent>> {
+        while self.events.is_empty() && !self.finished {
+            self.advance().await?;
+        }
+        Ok(self.events.pop_front())
+    }
+
+    // The next caption, or None once the input has been read completely.
+    // Segments that fail and stream errors are returned as errors; reading
+    // can go on after them.
+    pub async fn next_caption(&mut self) -> Result<Option<Caption>> {
+        while self.captions.is_empty() {
+            let Some(event) = self.next_event().await? else {
+                return Ok(None);
+            };
+            
+            match event {
+                ReaderEvent::Segment(segment_captions) => {
+                    if let Some(e) = segment_captions.error {
+                        return Err(e.context(format!("Failed to read segment {}", segment_captions.segment.uri)));
+                    }
+                    let segment = segment_captions.segment;
+                    self.captions.extend(segment_captions.captions.into_iter().map(|event| Caption {
+                        event,
+                        segment: Some(segment.clone()),
+                    }));
+                }
+                ReaderEvent::Captions(decoded) | ReaderEvent::Finished(decoded) => {
+                    self.captions.extend(decoded.captions.into_iter().map(|event| Caption { event, segment: None }));
+                }
+                ReaderEvent::Error(e) => return Err(e),
+                ReaderEvent::SegmentsFound(_) | ReaderEvent::SegmentStarted { .. } | ReaderEvent::PollFinished => {}
+            }
+        }
+        Ok(self.captions.pop_front())
+    }
+
+    // Ends the input early, as when a stream is stopped: decodes the data
+    // received so far and returns the captions still on screen
+    pub fn finish(&mut self) -> Result<DecodedCaptions> {
+        self.finished = true;
+        let mut captions = self.extractor.end_ts_data()?;
+        captions.extend(self.extractor.flush()?);
+        Ok(self.stream_captions(captions))
+    }
+    
+    async fn advance(&mut self) -> Result<()> {
+        if let Some(segment) = self.started_segment.take() {
+            let segment_captions = self.read_segment(segment).await;
+            self.events.push_back(ReaderEvent::Segment(Box::new(segment_captions)));
+            return Ok(());
+        }
+        
+        if let Some(segment) = self.segments.pop_front() {
+            info!("Processing segment: {}", segment.uri);
+            self.segment_number += 1;
+            self.events.push_back(ReaderEvent::SegmentStarted { number: self.segment_number, uri: segment.uri.clone() });
+            self.started_segment = Some(segment);
+            return Ok(());
+        }
+        
+        match &mut self.source {
+            Source::Segments => {
+                info!("Completed processing all segments");
+                let captions = self.finish()?;
+                self.events.push_back(ReaderEvent::Finished(captions));
+            }
+            Source::Live(playlist) => {
+                if playlist.reloaded {
+                    info!("Completed live playlist poll cycle");
+                    playlist.reloaded = false;
+                    self.events.push_back(ReaderEvent::PollFinished);
+                    return Ok(());
+                }
+                
+                if playlist.polled {
+                    sleep(playlist.poll_interval).await;
+                } else {
+                    info!("Starting live playlist polling every {}s", playlist.poll_interval.as_secs());
+                }
+                playlist.polled = true;
+                playlist.reloaded = true;
+                
+                match self.hls_parser.get_lowest_bitrate_segments(&playlist.url).await {
+                    Ok(segments) => {
+                        for segment in segments {
+                            if playlist.seen_segments.insert(segment.uri.clone()) {
+                                self.segments.push_back(segment);
+                            }
+                        }
+                    }
+                    Err(e) => self.events.push_back(ReaderEvent::Error(e)),
+                }
+            }
+            Source::Udp(socket, datagram) => {
+                let length = socket.recv(datagram).await?;
+                let Some(payload) = udp_input::ts_payload(&datagram[..length]) else {
+                    debug!("Ignoring {} byte datagram that is neither MPEG-TS nor RTP", length);
+                    return Ok(());
+                };
+                
+                let result = self.extractor.push_ts_data(payload);
+                self.push_stream_result(result);
+            }
+            Source::Bytes(body) => match body.chunk().await {
+                Ok(Some(chunk)) => {
+                    let result = self.extractor.push_ts_data(&chunk);
+                    self.push_stream_result(result);
+                }
+                Ok(None) => {
+                    let captions = self.finish()?;
+                    self.events.push_back(ReaderEvent::Finished(captions));
+                }
+                Err(e) => self.events.push_back(ReaderEvent::Error(e)),
+            },
+        }
+        Ok(())
+    }
+
+    fn push_stream_result(&mut self, result: Result<Vec<CaptionEvent>>) {
+        match result {
+            Ok(captions) => {
+                let decoded = self.stream_captions(captions);
+                if !decoded.captions.is_empty() || !decoded.cc_data.is_empty() {
+                    self.events.push_back(ReaderEvent::Captions(decoded));
+                }
+            }
+            Err(e) => self.events.push_back(ReaderEvent::Error(e)),
+        }
+    }
+
+    fn stream_captions(&mut self, captions: Vec<CaptionEvent>) -> DecodedCaptions {
+        // Service data is only handed out per segment; drop it so that it
+        // does not pile up while a stream runs
+        for service in self.extractor.take_services_seen() {
+            self.extractor.take_service_data(service);
+        }
+        
+        DecodedCaptions {
+            captions,
+            cc_data: self.extractor.take_cc_data(),
+        }
+    }
+    
+    async fn read_segment(&mut self, segment: Segment) -> SegmentCaptions {
+        let result = self.decode_segment(&segment).await;
+        if result.is_err() {
+            // Leftovers of a failed segment would otherwise be completed by
+            // the data of the next one
+            self.extractor.discard_ts_data();
+        }
+        
+        let channels = self.extractor.take_channels_seen();
+        let services = self.extractor.take_services_seen()
+            .into_iter()
+            .map(|service| (service, self.extractor.take_service_data(service)))
+            .collect();
+        let cc_data = self.extractor.take_cc_data();
+        
+        let (captions, error) = match result {
+            Ok(captions) => (captions, None),
+            Err(e) => (Vec::new(), Some(e)),
+        };
+        
+        SegmentCaptions {
+            segment,
+            captions,
+            channels,
+            services,
+            cc_data,
+            error,
+        }
+    }
+    
+    async fn decode_segment(&mut self, segment: &Segment) -> Result<Vec<CaptionEvent>> {
+        let extractor = &mut self.extractor;
+        let mut captions = Vec::new();
+        if segment.discontinuity {
+            captions.extend(extractor.mark_discontinuity()?);
+        }
+        if segment.playlist_timing {
+            extractor.set_segment_offset(segment.start_time);
+        }
+        
+        // Clear transport stream segments are parsed while they download, and
+        // the download is abandoned once the rest of the segment is not needed
+        if segment.key.is_none() && segment.init_segment.is_none() {
+            let mut body = self.hls_parser.open(&segment.uri).await?;
+            let mut received = 0;
+            while let Some(chunk) = body.chunk().await? {
+                received += chunk.len();
+                captions.extend(extractor.push_ts_data(&chunk)?);
+                if extractor.can_stop() {
+                    debug!("Stopped reading {} after {} bytes", segment.uri, received);
+                    break;
+                }
+            }
+            captions.extend(extractor.end_ts_data()?);
+            return Ok(captions);
+        }
+        
+        let mut segment_data = self.hls_parser.fetch(&segment.uri).await?;
+        if let Some(key) = &segment.key {
+            let key_data = self.hls_parser.fetch_key(&key.uri).await?;
+            segment_data = encryption::decrypt_aes128(&key_data, &key.iv, &segment_data)?.into();
+        }
+        
+        // Fragmented MP4 segments are described by their EXT-X-MAP init segment
+        if let Some(init_segment) = &segment.init_segment {
+            let init_data = self.hls_parser.fetch_init_segment(init_segment).await?;
+            let track = fmp4::parse_init_segment(&init_data)?;
+            captions.extend(extractor.parse_fmp4_segment(&track, &segment_data)?);
+            return Ok(captions);
+        }
+        
+        // Decrypted transport stream segments
+        captions.extend(extractor.parse_ts_file(&segment_data)?);
+        Ok(captions)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use url::Url;
+    use crate::caption_extractor::tests::{caption_stream, pop_on_pairs};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hlscaptionfinder-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn file_segment(dir: &std::path::Path, name: &str, data: &[u8], sequence_number: u64) -> Segment {
+        let path = dir.join(name);
+        std::fs::write(&path, data).unwrap();
+        Segment::standalone(Url::from_file_path(&path).unwrap().to_string(), sequence_number)
+    }
+
+    // "HI" goes on screen in the first segment and is erased in the second
+    fn caption_segments() -> (Vec<u8>, Vec<u8>) {
+        let mut erase = vec![(0x80, 0x80); 6];
+        erase.extend([(0x14, 0x2C), (0x14, 0x2C)]);
+        (caption_stream(0x1B, &pop_on_pairs()[..5]), caption_stream(0x1B, &erase))
+    }
+
+    async fn open_segments(segments: Vec<Segment>, options: ReaderOptions) -> CaptionReader {
+        CaptionReader::open_input(Client::new(), Input::Segments(segments), options).await.unwrap()
+    }
+
+    // Serves one response that promises more data than it sends and then
+    // closes the connection
+    async fn truncated_server(data: Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 4096];
+            let _ = socket.read(&mut request).await.unwrap();
+            let header = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", data.len() + 1000);
+            socket.write_all(header.as_bytes()).await.unwrap();
+            socket.write_all(&data).await.unwrap();
+        });
+        format!("http://{}/segment.ts", address)
+    }
+
+    #[tokio::test]
+    async fn segments_are_read_in_order() {
+        let dir = test_dir("reader-events");
+        let (first, second) = caption_segments();
+        let segments = vec![file_segment(&dir, "a.ts", &first, 0), file_segment(&dir, "b.ts", &second, 1)];
+        let uris: Vec<_> = segments.iter().map(|segment| segment.uri.clone()).collect();
+        let mut reader = open_segments(segments, ReaderOptions::default()).await;
+        assert_eq!(reader.kind(), SourceKind::Segments);
+        
+        let mut captions = Vec::new();
+        let mut events = Vec::new();
+        while let Some(event) = reader.next_event().await.unwrap() {
+            events.push(match event {
+                ReaderEvent::SegmentsFound(count) => format!("found {}", count),
+                ReaderEvent::SegmentStarted { number, uri } => format!("started {} {}", number, uri == uris[number - 1]),
+                ReaderEvent::Segment(segment) => {
+                    assert!(segment.error.is_none());
+                    captions.extend(segment.captions.iter().map(|caption| (segment.segment.sequence_number, caption.text.clone())));
+                    format!("segment {} {:?}", segment.segment.sequence_number, segment.channels)
+                }
+                ReaderEvent::Finished(decoded) => format!("finished {}", decoded.captions.len()),
+                ReaderEvent::Captions(_) | ReaderEvent::Error(_) | ReaderEvent::PollFinished => "unexpected".to_string(),
+            });
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        
+        assert_eq!(events, [
+            "found 2",
+            "started 1 true",
+            "segment 0 [Cc1]",
+            "started 2 true",
+            "segment 1 []",
+            "finished 0",
+        ]);
+        assert_eq!(captions, [(1, "HI".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn next_caption_comes_with_its_segment() {
+        let dir = test_dir("reader-captions");
+        let (first, second) = caption_segments();
+        let segments = vec![file_segment(&dir, "a.ts", &first, 0), file_segment(&dir, "b.ts", &second, 1)];
+        let mut reader = open_segments(segments, ReaderOptions::default()).await;
+        
+        let caption = reader.next_caption().await.unwrap().unwrap();
+        let end = reader.next_caption().await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        
+        assert_eq!(caption.event.text, "HI");
+        assert_eq!(caption.segment.map(|segment| segment.sequence_number), Some(1));
+        assert!(end.is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_download_does_not_leak_into_next_segment() {
+        let dir = test_dir("reader-truncated");
+        let (first, second) = caption_segments();
+        
+        // The first segment breaks off in the middle of a packet
+        let url = truncated_server(first[..first.len() - 100].to_vec()).await;
+        let segments = vec![Segment::standalone(url, 0), file_segment(&dir, "b.ts", &second, 1)];
+        let options = ReaderOptions {
+            reorder_depth: 0,
+            record_cc_data: true,
+            ..Default::default()
+        };
+        let mut reader = open_segments(segments, options).await;
+        
+        let mut results = Vec::new();
+        while let Some(event) = reader.next_event().await.unwrap() {
+            if let ReaderEvent::Segment(segment) = event {
+                results.push((segment.error.is_some(), segment.cc_data.len()));
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        
+        // Only the frames of the second segment are decoded with it
+        assert_eq!(results.len(), 2);
+        assert!(results[0].0);
+        assert_eq!(results[1], (false, 8));
+    }
+
+    #[tokio::test]
+    async fn finish_ends_a_stream_early() {
+        let (mut sender, receiver) = tokio::io::duplex(64 * 1024);
+        sender.write_all(&caption_segments().0).await.unwrap();
+        let mut reader = CaptionReader::from_reader(receiver, ReaderOptions::default());
+        assert_eq!(reader.kind(), SourceKind::Stream);
+        
+        // The caption is still on screen when the stream stops sending
+        let waited = tokio::time::timeout(Duration::from_millis(200), reader.next_event()).await;
+        assert!(waited.is_err());
+        
+        let decoded = reader.finish().unwrap();
+        assert_eq!(decoded.captions.len(), 1);
+        assert_eq!(decoded.captions[0].text, "HI");
+        assert!(reader.next_event().await.unwrap().is_none());
+    }
+}
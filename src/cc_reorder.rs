@@ -0,0 +1,104 @@
+use crate::caption_event::CaptionTime;
+use crate::cea708::CaptionData;
+
+pub const DEFAULT_REORDER_DEPTH: usize = 4;
+
+// Caption data is carried in decode order, but with B-frames it has to be
+// replayed in presentation order or the characters come out scrambled. The
+// buffer holds the cc_data of the last few access units sorted by PTS and
+// releases the earliest once more than `depth` are pending.
+pub struct CcReorderBuffer {
+    depth: usize,
+    pending: Vec<(CaptionTime, Vec<CaptionData>)>,
+}
+
+impl CcReorderBuffer {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+    }
+
+    pub fn push(&mut self, time: CaptionTime, caption_data: Vec<CaptionData>) -> Vec<(CaptionTime, Vec<CaptionData>)> {
+        // Several SEI messages of one access unit share its PTS
+        match self.pending.binary_search_by_key(&time.pts, |(pending_time, _)| pending_time.pts) {
+            Ok(index) => self.pending[index].1.extend(caption_data),
+            Err(index) => self.pending.insert(index, (time, caption_data)),
+        }
+        
+        let ready = self.pending.len().saturating_sub(self.depth);
+        self.pending.drain(..ready).collect()
+    }
+
+    // Releases everything, e.g. at a segment or discontinuity boundary
+    pub fn flush(&mut self) -> Vec<(CaptionTime, Vec<CaptionData>)> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(frame: u64) -> CaptionTime {
+        CaptionTime {
+            pts: frame * 3003,
+            seconds: frame as f64 * 1001.0 / 30000.0,
+        }
+    }
+
+    // One cc_data pair tagged with its frame number
+    fn frame_data(frame: u8) -> Vec<CaptionData> {
+        vec![CaptionData {
+            cc_valid: true,
+            cc_type: 0,
+            cc_data: [frame, 0],
+        }]
+    }
+
+    fn frames(released: Vec<(CaptionTime, Vec<CaptionData>)>) -> Vec<u8> {
+        released
+            .into_iter()
+            .flat_map(|(_, caption_data)| caption_data.into_iter().map(|data| data.cc_data[0]))
+            .collect()
+    }
+
+    #[test]
+    fn restores_presentation_order_of_b_frames() {
+        let mut buffer = CcReorderBuffer::new(2);
+        let mut released = Vec::new();
+        // I0 P3 B1 B2 P6 B4 B5 in decode order
+        for frame in [0, 3, 1, 2, 6, 4, 5] {
+            released.extend(frames(buffer.push(time(frame as u64), frame_data(frame))));
+        }
+        assert_eq!(released, vec![0, 1, 2, 3, 4]);
+        
+        // The frames still held back come out in order at a flush
+        assert_eq!(frames(buffer.flush()), vec![5, 6]);
+        assert!(buffer.flush().is_empty());
+    }
+
+    #[test]
+    fn merges_caption_data_of_one_access_unit() {
+        let mut buffer = CcReorderBuffer::new(1);
+        assert!(buffer.push(time(0), frame_data(1)).is_empty());
+        assert!(buffer.push(time(0), frame_data(2)).is_empty());
+        
+        let released = buffer.push(time(1), frame_data(3));
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].0, time(0));
+        assert_eq!(frames(released), vec![1, 2]);
+    }
+
+    #[test]
+    fn depth_zero_keeps_decode_order() {
+        let mut buffer = CcReorderBuffer::new(0);
+        assert_eq!(frames(buffer.push(time(3), frame_data(3))), vec![3]);
+        assert_eq!(frames(buffer.push(time(1), frame_data(1))), vec![1]);
+    }
+}
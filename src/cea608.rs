@@ -1,195 +1,890 @@
-#![allow(dead_code)]
+use std::collections::{BTreeMap, BTreeSet};
 use anyhow::Result;
 use log::debug;
+use crate::caption_event::{CaptionCell, CaptionChannel, CaptionColor, CaptionEvent, CaptionLine, CaptionStyle, CaptionTime};
 use crate::cea708::CaptionData;
 
+pub const SCREEN_ROWS: usize = 15;
+pub const SCREEN_COLUMNS: usize = 32;
+
+const DEFAULT_BASE_ROW: usize = SCREEN_ROWS - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptionMode {
+    PopOn,
+    RollUp(u8),
+    PaintOn,
+}
+
+// One caption memory: 15 rows of 32 columns. Empty cells are transparent.
+// The cells stay inside the decoder; captions carry their layout as the
+// CaptionEvent lines.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cea608Screen {
+    cells: [[Option<CaptionCell>; SCREEN_COLUMNS]; SCREEN_ROWS],
+}
+
+impl Cea608Screen {
+    pub fn new() -> Self {
+        Self {
+            cells: [[None; SCREEN_COLUMNS]; SCREEN_ROWS],
+        }
+    }
+
+    pub fn set(&mut self, row: usize, column: usize, cell: Option<CaptionCell>) {
+        self.cells[row][column] = cell;
+    }
+
+    pub fn clear(&mut self) {
+        self.cells = [[None; SCREEN_COLUMNS]; SCREEN_ROWS];
+    }
+
+    pub fn clear_row(&mut self, row: usize) {
+        self.cells[row] = [None; SCREEN_COLUMNS];
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.iter().all(|row| row.iter().all(|cell| cell.is_none()))
+    }
+
+    pub fn row_text(&self, row: usize) -> String {
+        self.line(row)
+            .map(|line| line.text().trim().to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn text(&self) -> String {
+        (0..SCREEN_ROWS)
+            .map(|row| self.row_text(row))
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn line(&self, row: usize) -> Option<CaptionLine> {
+        CaptionLine::from_cells(row, 0, &self.cells[row])
+    }
+
+    pub fn lines(&self) -> Vec<CaptionLine> {
+        (0..SCREEN_ROWS)
+            .filter_map(|row| self.line(row))
+            .filter(|line| !line.text().trim().is_empty())
+            .collect()
+    }
+}
+
+// Stateful CEA-608 decoder for a single caption or text channel. Characters
+// are written into displayed or non-displayed memory depending on the
+// current mode, and a caption is emitted each time the displayed memory is
+// erased, swapped or scrolled, covering the time it was visible.
 pub struct Cea608Decoder {
-    // Simple state for basic character decoding
-    // In a full implementation, this would include roll-up/pop-on mode, 
-    // positioning, styling, etc.
+    channel: CaptionChannel,
+    mode: CaptionMode,
+    displayed: Cea608Screen,
+    non_displayed: Cea608Screen,
+    row: usize,
+    column: usize,
+    base_row: usize,
+    pen: CaptionStyle,
+    displayed_since: Option<CaptionTime>,
 }
 
 impl Cea608Decoder {
+    pub fn new(channel: CaptionChannel) -> Self {
+        // Text services behave like a full-screen roll-up window
+        let mode = if channel.is_text() {
+            CaptionMode::RollUp(SCREEN_ROWS as u8)
+        } else {
+            CaptionMode::PopOn
+        };
+
+        Self {
+            channel,
+            mode,
+            displayed: Cea608Screen::new(),
+            non_displayed: Cea608Screen::new(),
+            row: DEFAULT_BASE_ROW,
+            column: 0,
+            base_row: DEFAULT_BASE_ROW,
+            pen: CaptionStyle::default(),
+            displayed_since: None,
+        }
+    }
+
+    // Decode one byte pair already routed to this channel. Redundant
+    // control codes must have been removed by the caller.
+    pub fn decode_pair(&mut self, data1: u8, data2: u8, timestamp: CaptionTime) -> Option<CaptionEvent> {
+        // Strip parity bit from both bytes
+        let data1 = data1 & 0x7F;
+        let data2 = data2 & 0x7F;
+
+        // Null padding
+        if data1 == 0x00 && data2 == 0x00 {
+            return None;
+        }
+
+        if (0x10..=0x1F).contains(&data1) {
+            return self.decode_control(data1, data2, timestamp);
+        }
+
+        if data1 < 0x20 {
+            return None;
+        }
+
+        self.write_char(standard_char(data1));
+        if data2 >= 0x20 {
+            self.write_char(standard_char(data2));
+        }
+        self.mark_displayed(timestamp);
+
+        None
+    }
+
+    pub fn flush(&mut self, timestamp: CaptionTime) -> Option<CaptionEvent> {
+        self.emit_displayed(timestamp)
+    }
+
+    fn decode_control(&mut self, data1: u8, data2: u8, timestamp: CaptionTime) -> Option<CaptionEvent> {
+        let code = data1 & 0xF7;
+        match (code, data2) {
+            (0x14 | 0x15, 0x20..=0x2F) => self.decode_misc_control(data2, timestamp),
+            (0x11, 0x20..=0x2F) => {
+                // Mid-row codes occupy a cell as a space and change the style
+                // of the characters that follow
+                self.write_char(' ');
+                self.decode_mid_row(data2);
+                None
+            }
+            (0x11, 0x30..=0x3F) => {
+                if let Some(ch) = special_char(data2) {
+                    self.write_char(ch);
+                    self.mark_displayed(timestamp);
+                }
+                None
+            }
+            (0x12 | 0x13, 0x20..=0x3F) => {
+                // Extended characters replace the standard fallback character
+                // that precedes them
+                if let Some(ch) = extended_char(code, data2) {
+                    self.backspace();
+                    self.write_char(ch);
+                    self.mark_displayed(timestamp);
+                }
+                None
+            }
+            (0x17, 0x21..=0x23) => {
+                // TO1, TO2, TO3 - tab offsets
+                self.column = (self.column + (data2 - 0x20) as usize).min(SCREEN_COLUMNS - 1);
+                None
+            }
+            (0x10..=0x17, 0x40..=0x7F) => {
+                self.decode_preamble_address(code, data2);
+                None
+            }
+            _ => {
+                debug!("CEA-608 control/command bytes: 0x{:02x} 0x{:02x}", data1, data2);
+                None
+            }
+        }
+    }
+
+    fn decode_misc_control(&mut self, data2: u8, timestamp: CaptionTime) -> Option<CaptionEvent> {
+        match data2 {
+            // RCL - resume caption loading
+            0x20 => {
+                self.mode = CaptionMode::PopOn;
+                None
+            }
+            // BS - backspace
+            0x21 => {
+                self.backspace();
+                None
+            }
+            // DER - delete to end of row
+            0x24 => {
+                let (row, column) = (self.row, self.column);
+                let memory = self.target_memory();
+                for col in column..SCREEN_COLUMNS {
+                    memory.set(row, col, None);
+                }
+                None
+            }
+            // RU2, RU3, RU4 - roll-up captions
+            0x25..=0x27 => {
+                let depth = data2 - 0x23;
+                let mut caption = None;
+
+                if !matches!(self.mode, CaptionMode::RollUp(_)) {
+                    caption = self.erase_displayed(timestamp);
+                    self.non_displayed.clear();
+                    self.base_row = DEFAULT_BASE_ROW;
+                }
+
+                self.mode = CaptionMode::RollUp(depth);
+                self.clear_outside_roll_up_window();
+                self.row = self.base_row;
+                self.column = 0;
+                caption
+            }
+            // RDC - resume direct captioning
+            0x29 => {
+                let caption = if matches!(self.mode, CaptionMode::RollUp(_)) {
+                    self.erase_displayed(timestamp)
+                } else {
+                    None
+                };
+                self.mode = CaptionMode::PaintOn;
+                caption
+            }
+            // TR - text restart clears the text service
+            0x2A => {
+                let caption = self.erase_displayed(timestamp);
+                self.row = self.base_row;
+                self.column = 0;
+                caption
+            }
+            // RTD - resume text display
+            0x2B => None,
+            // EDM - erase displayed memory
+            0x2C => self.erase_displayed(timestamp),
+            // CR - carriage return
+            0x2D => {
+                match self.mode {
+                    CaptionMode::RollUp(depth) => {
+                        let caption = self.emit_displayed(timestamp);
+                        self.roll_up(depth);
+                        self.column = 0;
+                        self.mark_displayed(timestamp);
+                        caption
+                    }
+                    _ => None,
+                }
+            }
+            // ENM - erase non-displayed memory
+            0x2E => {
+                self.non_displayed.clear();
+                None
+            }
+            // EOC - end of caption, flip memories
+            0x2F => {
+                let caption = self.emit_displayed(timestamp);
+                std::mem::swap(&mut self.displayed, &mut self.non_displayed);
+                self.mode = CaptionMode::PopOn;
+                self.mark_displayed(timestamp);
+                caption
+            }
+            _ => {
+                debug!("Ignoring CEA-608 miscellaneous control code 0x{:02x}", data2);
+                None
+            }
+        }
+    }
+
+    fn decode_preamble_address(&mut self, code: u8, data2: u8) {
+        let Some(row) = preamble_row(code, data2) else {
+            return;
+        };
+
+        // Bit 0 is underline, the remaining four bits select either a
+        // color (with italics as the eighth "color") or an indent
+        let attributes = data2 & 0x1F;
+        let underline = attributes & 0x01 != 0;
+        let mut column = 0;
+
+        self.pen = if attributes >= 0x10 {
+            column = ((attributes - 0x10) >> 1) as usize * 4;
+            CaptionStyle { underline, ..CaptionStyle::default() }
+        } else {
+            match style_color(attributes >> 1) {
+                Some(color) => CaptionStyle { color, italics: false, underline },
+                None => CaptionStyle { color: CaptionColor::White, italics: true, underline },
+            }
+        };
+
+        if let CaptionMode::RollUp(depth) = self.mode {
+            let base_row = row.max(depth as usize - 1);
+            if base_row != self.base_row {
+                self.move_roll_up_window(base_row, depth);
+            }
+            self.row = self.base_row;
+        } else {
+            self.row = row;
+        }
+        self.column = column;
+    }
+
+    fn decode_mid_row(&mut self, data2: u8) {
+        let underline = data2 & 0x01 != 0;
+        self.pen = match style_color((data2 >> 1) & 0x07) {
+            Some(color) => CaptionStyle { color, italics: false, underline },
+            // Italics keeps the current color
+            None => CaptionStyle { italics: true, underline, ..self.pen },
+        };
+    }
+
+    fn target_memory(&mut self) -> &mut Cea608Screen {
+        match self.mode {
+            CaptionMode::PopOn => &mut self.non_displayed,
+            CaptionMode::RollUp(_) | CaptionMode::PaintOn => &mut self.displayed,
+        }
+    }
+
+    fn write_char(&mut self, ch: char) {
+        // Characters past the last column keep overwriting it
+        let column = self.column.min(SCREEN_COLUMNS - 1);
+        let row = self.row;
+        let cell = CaptionCell { ch, style: self.pen };
+        self.target_memory().set(row, column, Some(cell));
+        self.column = (column + 1).min(SCREEN_COLUMNS);
+    }
+
+    fn backspace(&mut self) {
+        if self.column > 0 {
+            self.column -= 1;
+            let (row, column) = (self.row, self.column);
+            self.target_memory().set(row, column, None);
+        }
+    }
+
+    fn roll_up(&mut self, depth: u8) {
+        let top = (self.base_row + 1).saturating_sub(depth as usize);
+        for row in top..self.base_row {
+            self.displayed.cells[row] = self.displayed.cells[row + 1];
+        }
+        self.displayed.clear_row(self.base_row);
+    }
+
+    fn move_roll_up_window(&mut self, base_row: usize, depth: u8) {
+        let depth = depth as usize;
+        let old_top = (self.base_row + 1).saturating_sub(depth);
+        let rows: Vec<_> = (old_top..=self.base_row)
+            .map(|row| self.displayed.cells[row])
+            .collect();
+
+        self.displayed.clear();
+
+        let new_top = (base_row + 1) - rows.len();
+        for (offset, cells) in rows.into_iter().enumerate() {
+            self.displayed.cells[new_top + offset] = cells;
+        }
+        self.base_row = base_row;
+    }
+
+    fn clear_outside_roll_up_window(&mut self) {
+        if let CaptionMode::RollUp(depth) = self.mode {
+            let top = (self.base_row + 1).saturating_sub(depth as usize);
+            for row in 0..SCREEN_ROWS {
+                if row < top || row > self.base_row {
+                    self.displayed.clear_row(row);
+                }
+            }
+        }
+    }
+
+    fn mark_displayed(&mut self, timestamp: CaptionTime) {
+        if self.displayed_since.is_none() && !self.displayed.is_empty() {
+            self.displayed_since = Some(timestamp);
+        }
+    }
+
+    fn erase_displayed(&mut self, timestamp: CaptionTime) -> Option<CaptionEvent> {
+        let caption = self.emit_displayed(timestamp);
+        self.displayed.clear();
+        caption
+    }
+
+    fn emit_displayed(&mut self, timestamp: CaptionTime) -> Option<CaptionEvent> {
+        let start = self.displayed_since.take()?;
+        let text = self.displayed.text();
+
+        if text.is_empty() {
+            return None;
+        }
+
+        Some(CaptionEvent {
+            channel: self.channel,
+            text,
+            start,
+            end: timestamp.max(start),
+            lines: self.displayed.lines(),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct FieldState {
+    data_channel: u8,
+    text_mode: [bool; 2],
+    last_control: Option<(u8, u8)>,
+    in_xds: bool,
+}
+
+// Routes byte pairs from both fields to independent CC1-CC4/T1-T4 decoders.
+// The data channel is selected by the channel bit of the most recent control
+// code in each field, and TR/RTD versus RCL/RU/RDC selects text or caption
+// service within that data channel.
+pub struct Cea608Demuxer {
+    fields: [FieldState; 2],
+    decoders: BTreeMap<CaptionChannel, Cea608Decoder>,
+    channels_seen: BTreeSet<CaptionChannel>,
+}
+
+impl Cea608Demuxer {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            fields: [
+                FieldState { data_channel: 1, ..FieldState::default() },
+                FieldState { data_channel: 1, ..FieldState::default() },
+            ],
+            decoders: BTreeMap::new(),
+            channels_seen: BTreeSet::new(),
+        }
     }
-    
-    pub fn decode_caption_data(&self, caption_data: &[CaptionData]) -> Result<Vec<String>> {
+
+    pub fn decode_caption_data(&mut self, caption_data: &[CaptionData], timestamp: CaptionTime) -> Result<Vec<CaptionEvent>> {
         let mut captions = Vec::new();
-        
+
         for data in caption_data {
             if !data.cc_valid {
                 continue;
             }
-            
+
             // CEA-608 uses cc_type 0 and 1 for field 1 and 2
             // cc_type 2 and 3 are for CEA-708 packet data
             if data.cc_type <= 1 {
-                if let Some(text) = self.decode_cea608_pair(data.cc_data[0], data.cc_data[1]) {
-                    if !text.is_empty() {
-                        captions.push(text);
-                    }
+                if let Some(caption) = self.decode_pair(data.cc_type + 1, data.cc_data[0], data.cc_data[1], timestamp) {
+                    captions.push(caption);
                 }
             }
         }
-        
+
         Ok(captions)
     }
-    
-    fn decode_cea608_pair(&self, data1: u8, data2: u8) -> Option<String> {
-        // This is a simplified CEA-608 decoder that focuses on basic character extraction
-        // A full implementation would handle:
-        // - Control codes (0x10-0x1F)
-        // - Extended characters
-        // - Special characters
-        // - Preamble address codes
-        // - Tab offsets
-        // - Caption positioning and styling
-        
-        let mut result = String::new();
-        
-        // Basic printable ASCII characters
-        if self.is_printable_basic(data1) {
-            if let Some(ch) = std::char::from_u32(data1 as u32) {
-                result.push(ch);
-            }
-        } else if self.is_special_character(data1, data2) {
-            // Handle special character pairs
-            if let Some(ch) = self.decode_special_character(data2) {
-                result.push(ch);
-            }
-        } else if self.is_extended_character(data1, data2) {
-            // Handle extended Western European characters
-            if let Some(ch) = self.decode_extended_character(data1, data2) {
-            result.push(ch);
+
+    pub fn decode_pair(&mut self, field: u8, data1: u8, data2: u8, timestamp: CaptionTime) -> Option<CaptionEvent> {
+        let state = &mut self.fields[(field.clamp(1, 2) - 1) as usize];
+        let byte1 = data1 & 0x7F;
+        let byte2 = data2 & 0x7F;
+
+        if byte1 == 0x00 && byte2 == 0x00 {
+            return None;
+        }
+
+        // XDS packets on field 2 interrupt caption data until their end code
+        if (0x01..=0x0F).contains(&byte1) {
+            state.in_xds = byte1 != 0x0F;
+            state.last_control = None;
+            return None;
+        }
+
+        if (0x10..=0x1F).contains(&byte1) {
+            // Control codes are transmitted twice; the redundant copy is ignored
+            if state.last_control == Some((byte1, byte2)) {
+                state.last_control = None;
+                return None;
+            }
+            state.last_control = Some((byte1, byte2));
+            state.in_xds = false;
+            state.data_channel = if byte1 & 0x08 != 0 { 2 } else { 1 };
+
+            if matches!(byte1 & 0xF7, 0x14 | 0x15) {
+                let text_mode = &mut state.text_mode[state.data_channel as usize - 1];
+                match byte2 {
+                    0x20 | 0x25..=0x27 | 0x29 => *text_mode = false,
+                    0x2A | 0x2B => *text_mode = true,
+                    _ => {}
+                }
             }
         } else {
-            // Check for control codes
-            debug!("CEA-608 control/command bytes: 0x{:02x} 0x{:02x}", data1, data2);
-        }
-        
-        if self.is_printable_basic(data2) && data1 >= 0x20 {
-            // Second byte is also a basic character
-            if let Some(ch) = std::char::from_u32(data2 as u32) {
-                result.push(ch);
+            state.last_control = None;
+            if state.in_xds || byte1 < 0x20 {
+                return None;
             }
         }
-        
-        if result.is_empty() {
-            None
-        } else {
-            Some(result)
+
+        let text_mode = state.text_mode[state.data_channel as usize - 1];
+        let channel = CaptionChannel::from_field(field, state.data_channel, text_mode);
+
+        if byte1 >= 0x20 {
+            self.channels_seen.insert(channel);
         }
+
+        self.decoders
+            .entry(channel)
+            .or_insert_with(|| Cea608Decoder::new(channel))
+            .decode_pair(data1, data2, timestamp)
     }
-    
-    fn is_printable_basic(&self, byte: u8) -> bool {
-        // Basic printable ASCII range for CEA-608
-        byte >= 0x20 && byte <= 0x7F
+
+    pub fn flush(&mut self, timestamp: CaptionTime) -> Vec<CaptionEvent> {
+        self.decoders
+            .values_mut()
+            .filter_map(|decoder| decoder.flush(timestamp))
+            .collect()
     }
-    
-    fn is_special_character(&self, data1: u8, data2: u8) -> bool {
-        // Special character codes: 0x11 0x30-0x3F
-        data1 == 0x11 && data2 >= 0x30 && data2 <= 0x3F
+
+    // Channels that carried printable characters since take_channels_seen
+    pub fn channels_seen(&self) -> impl Iterator<Item = CaptionChannel> + '_ {
+        self.channels_seen.iter().copied()
     }
-    
-    fn decode_special_character(&self, data2: u8) -> Option<char> {
-        // CEA-608 special characters
-        match data2 {
-            0x30 => Some('®'),  // Registered mark
-            0x31 => Some('°'),  // Degree sign
-            0x32 => Some('½'),  // 1/2
-            0x33 => Some('¿'),  // Inverted question mark
-            0x34 => Some('™'),  // Trademark
-            0x35 => Some('¢'),  // Cents sign
-            0x36 => Some('£'),  // Pounds sign
-            0x37 => Some('♪'),  // Music note
-            0x38 => Some('à'),  // a grave
-            0x39 => Some(' '),  // Transparent space
-            0x3A => Some('è'),  // e grave
-            0x3B => Some('â'),  // a circumflex
-            0x3C => Some('ê'),  // e circumflex
-            0x3D => Some('î'),  // i circumflex
-            0x3E => Some('ô'),  // o circumflex
-            0x3F => Some('û'),  // u circumflex
-            _ => None,
-        }
-    }
-    
-    fn is_extended_character(&self, data1: u8, data2: u8) -> bool {
-        // Extended character codes: 0x12 0x20-0x3F or 0x13 0x20-0x3F
-        (data1 == 0x12 || data1 == 0x13) && data2 >= 0x20 && data2 <= 0x3F
-    }
-    
-    fn decode_extended_character(&self, data1: u8, data2: u8) -> Option<char> {
-        // Extended Western European character set
-        match (data1, data2) {
-            (0x12, 0x20) => Some('Á'), // A acute
-            (0x12, 0x21) => Some('É'), // E acute
-            (0x12, 0x22) => Some('Ó'), // O acute
-            (0x12, 0x23) => Some('Ú'), // U acute
-            (0x12, 0x24) => Some('Ü'), // U diaeresis
-            (0x12, 0x25) => Some('ü'), // u diaeresis
-            (0x12, 0x26) => Some('´'), // Acute accent
-            (0x12, 0x27) => Some('¡'), // Inverted exclamation
-            (0x12, 0x28) => Some('*'), // Asterisk
-            (0x12, 0x29) => Some('\''), // Apostrophe
-            (0x12, 0x2A) => Some('—'), // Em dash
-            (0x12, 0x2B) => Some('©'), // Copyright
-            (0x12, 0x2C) => Some('℠'), // Service mark
-            (0x12, 0x2D) => Some('•'), // Bullet
-            (0x12, 0x2E) => Some('"'), // Left double quote
-            (0x12, 0x2F) => Some('"'), // Right double quote
-            (0x12, 0x30) => Some('À'), // A grave
-            (0x12, 0x31) => Some('Â'), // A circumflex
-            (0x12, 0x32) => Some('Ç'), // C cedilla
-            (0x12, 0x33) => Some('È'), // E grave
-            (0x12, 0x34) => Some('Ê'), // E circumflex
-            (0x12, 0x35) => Some('Ë'), // E diaeresis
-            (0x12, 0x36) => Some('ë'), // e diaeresis
-            (0x12, 0x37) => Some('Î'), // I circumflex
-            (0x12, 0x38) => Some('Ï'), // I diaeresis
-            (0x12, 0x39) => Some('ï'), // i diaeresis
-            (0x12, 0x3A) => Some('Ô'), // O circumflex
-            (0x12, 0x3B) => Some('Ù'), // U grave
-            (0x12, 0x3C) => Some('ù'), // u grave
-            (0x12, 0x3D) => Some('Û'), // U circumflex
-            (0x12, 0x3E) => Some('«'), // Left guillemet
-            (0x12, 0x3F) => Some('»'), // Right guillemet
-            
-            (0x13, 0x20) => Some('Ã'), // A tilde
-            (0x13, 0x21) => Some('ã'), // a tilde
-            (0x13, 0x22) => Some('Í'), // I acute
-            (0x13, 0x23) => Some('Ì'), // I grave
-            (0x13, 0x24) => Some('ì'), // i grave
-            (0x13, 0x25) => Some('Ò'), // O grave
-            (0x13, 0x26) => Some('ò'), // o grave
-            (0x13, 0x27) => Some('Õ'), // O tilde
-            (0x13, 0x28) => Some('õ'), // o tilde
-            (0x13, 0x29) => Some('{'), // Left brace
-            (0x13, 0x2A) => Some('}'), // Right brace
-            (0x13, 0x2B) => Some('\\'), // Backslash
-            (0x13, 0x2C) => Some('^'), // Caret
-            (0x13, 0x2D) => Some('_'), // Underscore
-            (0x13, 0x2E) => Some('|'), // Pipe
-            (0x13, 0x2F) => Some('~'), // Tilde
-            (0x13, 0x30) => Some('Ä'), // A diaeresis
-            (0x13, 0x31) => Some('ä'), // a diaeresis
-            (0x13, 0x32) => Some('Ö'), // O diaeresis
-            (0x13, 0x33) => Some('ö'), // o diaeresis
-            (0x13, 0x34) => Some('ß'), // Sharp s
-            (0x13, 0x35) => Some('¥'), // Yen sign
-            (0x13, 0x36) => Some('¤'), // Generic currency
-            (0x13, 0x37) => Some('¦'), // Broken bar
-            (0x13, 0x38) => Some('Å'), // A ring
-            (0x13, 0x39) => Some('å'), // a ring
-            (0x13, 0x3A) => Some('Ø'), // O slash
-            (0x13, 0x3B) => Some('ø'), // o slash
-            (0x13, 0x3C) => Some('┌'), // Box drawing
-            (0x13, 0x3D) => Some('┐'), // Box drawing
-            (0x13, 0x3E) => Some('└'), // Box drawing
-            (0x13, 0x3F) => Some('┘'), // Box drawing
-            
-            _ => None,
-        }
-    }
-}
\ No newline at end of file
+
+    // Channels that carried printable characters since the last call
+    pub fn take_channels_seen(&mut self) -> Vec<CaptionChannel> {
+        std::mem::take(&mut self.channels_seen).into_iter().collect()
+    }
+}
+
+// Colors 0-6 shared by preamble address and mid-row codes; 7 selects italics
+fn style_color(index: u8) -> Option<CaptionColor> {
+    match index {
+        0 => Some(CaptionColor::White),
+        1 => Some(CaptionColor::Green),
+        2 => Some(CaptionColor::Blue),
+        3 => Some(CaptionColor::Cyan),
+        4 => Some(CaptionColor::Red),
+        5 => Some(CaptionColor::Yellow),
+        6 => Some(CaptionColor::Magenta),
+        _ => None,
+    }
+}
+
+// Preamble address code row numbers (0-based) for channel 1 first bytes
+fn preamble_row(code: u8, data2: u8) -> Option<usize> {
+    let second_half = data2 >= 0x60;
+    let row = match code {
+        0x11 => 1,
+        0x12 => 3,
+        0x15 => 5,
+        0x16 => 7,
+        0x17 => 9,
+        0x10 if !second_half => return Some(10),
+        0x13 => 12,
+        0x14 => 14,
+        _ => return None,
+    };
+
+    Some(if second_half { row } else { row - 1 })
+}
+
+// The CEA-608 basic character set differs from ASCII in a handful of positions
+fn standard_char(byte: u8) -> char {
+    match byte {
+        0x2A => 'á',
+        0x5C => 'é',
+        0x5E => 'í',
+        0x5F => 'ó',
+        0x60 => 'ú',
+        0x7B => 'ç',
+        0x7C => '÷',
+        0x7D => 'Ñ',
+        0x7E => 'ñ',
+        0x7F => '█',
+        _ => byte as char,
+    }
+}
+
+fn special_char(data2: u8) -> Option<char> {
+    // CEA-608 special characters
+    match data2 {
+        0x30 => Some('®'),  // Registered mark
+        0x31 => Some('°'),  // Degree sign
+        0x32 => Some('½'),  // 1/2
+        0x33 => Some('¿'),  // Inverted question mark
+        0x34 => Some('™'),  // Trademark
+        0x35 => Some('¢'),  // Cents sign
+        0x36 => Some('£'),  // Pounds sign
+        0x37 => Some('♪'),  // Music note
+        0x38 => Some('à'),  // a grave
+        0x39 => Some(' '),  // Transparent space
+        0x3A => Some('è'),  // e grave
+        0x3B => Some('â'),  // a circumflex
+        0x3C => Some('ê'),  // e circumflex
+        0x3D => Some('î'),  // i circumflex
+        0x3E => Some('ô'),  // o circumflex
+        0x3F => Some('û'),  // u circumflex
+        _ => None,
+    }
+}
+
+fn extended_char(data1: u8, data2: u8) -> Option<char> {
+    // Extended Western European character set
+    match (data1, data2) {
+        (0x12, 0x20) => Some('Á'), // A acute
+        (0x12, 0x21) => Some('É'), // E acute
+        (0x12, 0x22) => Some('Ó'), // O acute
+        (0x12, 0x23) => Some('Ú'), // U acute
+        (0x12, 0x24) => Some('Ü'), // U diaeresis
+        (0x12, 0x25) => Some('ü'), // u diaeresis
+        (0x12, 0x26) => Some('´'), // Acute accent
+        (0x12, 0x27) => Some('¡'), // Inverted exclamation
+        (0x12, 0x28) => Some('*'), // Asterisk
+        (0x12, 0x29) => Some('\''), // Apostrophe
+        (0x12, 0x2A) => Some('—'), // Em dash
+        (0x12, 0x2B) => Some('©'), // Copyright
+        (0x12, 0x2C) => Some('℠'), // Service mark
+        (0x12, 0x2D) => Some('•'), // Bullet
+        (0x12, 0x2E) => Some('“'), // Left double quote
+        (0x12, 0x2F) => Some('”'), // Right double quote
+        (0x12, 0x30) => Some('À'), // A grave
+        (0x12, 0x31) => Some('Â'), // A circumflex
+        (0x12, 0x32) => Some('Ç'), // C cedilla
+        (0x12, 0x33) => Some('È'), // E grave
+        (0x12, 0x34) => Some('Ê'), // E circumflex
+        (0x12, 0x35) => Some('Ë'), // E diaeresis
+        (0x12, 0x36) => Some('ë'), // e diaeresis
+        (0x12, 0x37) => Some('Î'), // I circumflex
+        (0x12, 0x38) => Some('Ï'), // I diaeresis
+        (0x12, 0x39) => Some('ï'), // i diaeresis
+        (0x12, 0x3A) => Some('Ô'), // O circumflex
+        (0x12, 0x3B) => Some('Ù'), // U grave
+        (0x12, 0x3C) => Some('ù'), // u grave
+        (0x12, 0x3D) => Some('Û'), // U circumflex
+        (0x12, 0x3E) => Some('«'), // Left guillemet
+        (0x12, 0x3F) => Some('»'), // Right guillemet
+
+        (0x13, 0x20) => Some('Ã'), // A tilde
+        (0x13, 0x21) => Some('ã'), // a tilde
+        (0x13, 0x22) => Some('Í'), // I acute
+        (0x13, 0x23) => Some('Ì'), // I grave
+        (0x13, 0x24) => Some('ì'), // i grave
+        (0x13, 0x25) => Some('Ò'), // O grave
+        (0x13, 0x26) => Some('ò'), // o grave
+        (0x13, 0x27) => Some('Õ'), // O tilde
+        (0x13, 0x28) => Some('õ'), // o tilde
+        (0x13, 0x29) => Some('{'), // Left brace
+        (0x13, 0x2A) => Some('}'), // Right brace
+        (0x13, 0x2B) => Some('\\'), // Backslash
+        (0x13, 0x2C) => Some('^'), // Caret
+        (0x13, 0x2D) => Some('_'), // Underscore
+        (0x13, 0x2E) => Some('|'), // Pipe
+        (0x13, 0x2F) => Some('~'), // Tilde
+        (0x13, 0x30) => Some('Ä'), // A diaeresis
+        (0x13, 0x31) => Some('ä'), // a diaeresis
+        (0x13, 0x32) => Some('Ö'), // O diaeresis
+        (0x13, 0x33) => Some('ö'), // o diaeresis
+        (0x13, 0x34) => Some('ß'), // Sharp s
+        (0x13, 0x35) => Some('¥'), // Yen sign
+        (0x13, 0x36) => Some('¤'), // Generic currency
+        (0x13, 0x37) => Some('¦'), // Broken bar
+        (0x13, 0x38) => Some('Å'), // A ring
+        (0x13, 0x39) => Some('å'), // a ring
+        (0x13, 0x3A) => Some('Ø'), // O slash
+        (0x13, 0x3B) => Some('ø'), // o slash
+        (0x13, 0x3C) => Some('┌'), // Box drawing
+        (0x13, 0x3D) => Some('┐'), // Box drawing
+        (0x13, 0x3E) => Some('└'), // Box drawing
+        (0x13, 0x3F) => Some('┘'), // Box drawing
+
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(frame: u64) -> CaptionTime {
+        CaptionTime {
+            pts: frame * 3003,
+            seconds: frame as f64 * 1001.0 / 30000.0,
+        }
+    }
+
+    // Feeds field 1 byte pairs, one per frame from the given frame on, and
+    // returns the captions they complete
+    fn decode(demuxer: &mut Cea608Demuxer, first_frame: u64, pairs: &[(u8, u8)]) -> Vec<CaptionEvent> {
+        decode_field(demuxer, 1, first_frame, pairs)
+    }
+
+    fn decode_field(demuxer: &mut Cea608Demuxer, field: u8, first_frame: u64, pairs: &[(u8, u8)]) -> Vec<CaptionEvent> {
+        pairs
+            .iter()
+            .zip(first_frame..)
+            .filter_map(|(&(data1, data2), frame)| demuxer.decode_pair(field, data1, data2, time(frame)))
+            .collect()
+    }
+
+    fn text_pairs(text: &str) -> Vec<(u8, u8)> {
+        text.as_bytes()
+            .chunks(2)
+            .map(|pair| (pair[0], pair.get(1).copied().unwrap_or(0)))
+            .collect()
+    }
+
+    // Control codes are sent twice
+    fn control(data1: u8, data2: u8) -> Vec<(u8, u8)> {
+        vec![(data1, data2), (data1, data2)]
+    }
+
+    // RCL, the text, EOC and EDM using the control codes of one data channel
+    fn pop_on(misc: u8, text: &str) -> Vec<(u8, u8)> {
+        let mut pairs = control(misc, 0x20);
+        pairs.extend(text_pairs(text));
+        pairs.extend(control(misc, 0x2F));
+        pairs.extend(control(misc, 0x2C));
+        pairs
+    }
+
+    #[test]
+    fn pop_on_caption_shows_from_eoc_until_edm() {
+        let mut demuxer = Cea608Demuxer::new();
+        let mut pairs = control(0x14, 0x20);
+        pairs.extend(control(0x14, 0x70));
+        pairs.extend(text_pairs("HELLO"));
+        assert!(decode(&mut demuxer, 0, &pairs).is_empty());
+
+        // Nothing is on screen until EOC flips the memories
+        assert!(decode(&mut demuxer, 10, &control(0x14, 0x2F)).is_empty());
+        let captions = decode(&mut demuxer, 20, &control(0x14, 0x2C));
+
+        assert_eq!(captions.len(), 1);
+        let caption = &captions[0];
+        assert_eq!(caption.channel, CaptionChannel::Cc1);
+        assert_eq!(caption.text, "HELLO");
+        assert_eq!(caption.start, time(10));
+        assert_eq!(caption.end, time(20));
+        assert_eq!(caption.lines.len(), 1);
+        assert_eq!(caption.lines[0].row, 14);
+    }
+
+    #[test]
+    fn roll_up_caption_scrolls_at_carriage_return() {
+        let mut demuxer = Cea608Demuxer::new();
+        let mut pairs = control(0x14, 0x25);
+        pairs.extend(text_pairs("ONE"));
+        assert!(decode(&mut demuxer, 0, &pairs).is_empty());
+
+        let first = decode(&mut demuxer, 10, &control(0x14, 0x2D));
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].text, "ONE");
+        assert_eq!(first[0].start, time(2));
+        assert_eq!(first[0].end, time(10));
+
+        // The first row moves up and stays on screen with the second
+        decode(&mut demuxer, 12, &text_pairs("TWO"));
+        let second = decode(&mut demuxer, 20, &control(0x14, 0x2D));
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].text, "ONE\nTWO");
+        assert_eq!(second[0].start, time(10));
+        let rows: Vec<usize> = second[0].lines.iter().map(|line| line.row).collect();
+        assert_eq!(rows, vec![13, 14]);
+    }
+
+    #[test]
+    fn preamble_address_sets_row_indent_and_style() {
+        let mut demuxer = Cea608Demuxer::new();
+        let mut pairs = control(0x14, 0x20);
+        // Row 3, indent 8
+        pairs.extend(control(0x12, 0x54));
+        pairs.extend(text_pairs("AB"));
+        // Row 14 in blue, underlined
+        pairs.extend(control(0x14, 0x45));
+        pairs.extend(text_pairs("CD"));
+        // Row 15 in italics
+        pairs.extend(control(0x14, 0x6E));
+        pairs.extend(text_pairs("EF"));
+        pairs.extend(control(0x14, 0x2F));
+        pairs.extend(control(0x14, 0x2C));
+        let captions = decode(&mut demuxer, 0, &pairs);
+
+        assert_eq!(captions.len(), 1);
+        let lines = &captions[0].lines;
+        assert_eq!(lines.len(), 3);
+        assert_eq!((lines[0].row, lines[0].column), (2, 8));
+        assert_eq!(lines[0].spans[0].style, CaptionStyle::default());
+        assert_eq!((lines[1].row, lines[1].column), (13, 0));
+        assert_eq!(lines[1].spans[0].style, CaptionStyle { color: CaptionColor::Blue, italics: false, underline: true });
+        assert_eq!((lines[2].row, lines[2].column), (14, 0));
+        assert_eq!(lines[2].spans[0].style, CaptionStyle { color: CaptionColor::White, italics: true, underline: false });
+        assert_eq!(captions[0].text, "AB\nCD\nEF");
+    }
+
+    #[test]
+    fn channel_bit_and_field_select_the_channel() {
+        // Misc control codes start with 0x14 on data channel 1 and 0x1C on 2
+        let cases = [
+            (1, 0x14, CaptionChannel::Cc1),
+            (1, 0x1C, CaptionChannel::Cc2),
+            (2, 0x14, CaptionChannel::Cc3),
+            (2, 0x1C, CaptionChannel::Cc4),
+        ];
+        for (field, misc, channel) in cases {
+            let mut demuxer = Cea608Demuxer::new();
+            let captions = decode_field(&mut demuxer, field, 0, &pop_on(misc, "HI"));
+
+            assert_eq!(captions.len(), 1, "{:?}", channel);
+            assert_eq!(captions[0].channel, channel);
+            assert_eq!(captions[0].text, "HI");
+            assert_eq!(demuxer.channels_seen().collect::<Vec<_>>(), vec![channel]);
+        }
+    }
+
+    #[test]
+    fn text_restart_selects_the_text_service() {
+        let mut demuxer = Cea608Demuxer::new();
+        // TR, then text ended by a carriage return
+        let mut pairs = control(0x14, 0x2A);
+        pairs.extend(text_pairs("INFO"));
+        pairs.extend(control(0x14, 0x2D));
+        // RCL switches data channel 1 back to CC1
+        pairs.extend(pop_on(0x14, "CAP"));
+        let captions = decode(&mut demuxer, 0, &pairs);
+
+        assert_eq!(captions.len(), 2);
+        assert_eq!(captions[0].channel, CaptionChannel::T1);
+        assert_eq!(captions[0].text, "INFO");
+        assert_eq!(captions[1].channel, CaptionChannel::Cc1);
+        assert_eq!(captions[1].text, "CAP");
+        assert_eq!(demuxer.take_channels_seen(), vec![CaptionChannel::Cc1, CaptionChannel::T1]);
+        assert_eq!(demuxer.channels_seen().count(), 0);
+    }
+
+    #[test]
+    fn xds_packet_interrupts_caption_data() {
+        let mut demuxer = Cea608Demuxer::new();
+        let mut pairs = control(0x14, 0x20);
+        pairs.extend(text_pairs("AB"));
+        // XDS start (current class, program name), printable packet data
+        // and the end code with its checksum
+        pairs.push((0x01, 0x03));
+        pairs.extend(text_pairs("XY"));
+        pairs.push((0x0F, 0x1D));
+        // Caption data resumes where it left off
+        pairs.extend(text_pairs("CD"));
+        pairs.extend(control(0x14, 0x2F));
+        pairs.extend(control(0x14, 0x2C));
+        let captions = decode_field(&mut demuxer, 2, 0, &pairs);
+
+        assert_eq!(captions.len(), 1);
+        assert_eq!(captions[0].channel, CaptionChannel::Cc3);
+        assert_eq!(captions[0].text, "ABCD");
+    }
+
+    #[test]
+    fn redundant_control_pair_is_ignored() {
+        let mut demuxer = Cea608Demuxer::new();
+        let mut pairs = control(0x14, 0x20);
+        pairs.extend(text_pairs("ABCD"));
+        // Each doubled backspace removes one character
+        pairs.extend(control(0x14, 0x21));
+        pairs.extend(control(0x14, 0x21));
+        // A doubled EOC flips the memories only once
+        pairs.extend(control(0x14, 0x2F));
+        let shown = decode(&mut demuxer, 0, &pairs);
+        assert!(shown.is_empty());
+
+        let captions = decode(&mut demuxer, 20, &control(0x14, 0x2C));
+        assert_eq!(captions.len(), 1);
+        assert_eq!(captions[0].text, "AB");
+
+        // A single copy is still acted on
+        let mut pairs = vec![(0x14, 0x20)];
+        pairs.extend(text_pairs("EF"));
+        pairs.push((0x14, 0x2F));
+        pairs.push((0x14, 0x2C));
+        let captions = decode(&mut demuxer, 30, &pairs);
+        assert_eq!(captions.len(), 1);
+        assert_eq!(captions[0].text, "EF");
+        assert_eq!(captions[0].start, time(32));
+        assert_eq!(captions[0].end, time(33));
+    }
+}
@@ -1,6 +1,7 @@
-#![allow(dead_code)]
+use std::collections::{BTreeMap, BTreeSet};
 use anyhow::Result;
-use log::debug;
+use log::{debug, warn};
+use crate::caption_event::CaptionTime;
 
 // ITU-T T.35 country codes
 const T35_COUNTRY_CODE_US: u8 = 0xB5;
@@ -9,6 +10,16 @@ const T35_COUNTRY_CODE_US: u8 = 0xB5;
 const ATSC_IDENTIFIER: [u8; 4] = [0x47, 0x41, 0x39, 0x34]; // "GA94"
 const USER_DATA_TYPE_CODE: u8 = 0x03;
 
+// MPEG-2 user data caption formats predating A/53
+const SCTE20_USER_DATA_TYPE_CODE: u8 = 0x03;
+const DVD_IDENTIFIER: [u8; 4] = [0x43, 0x43, 0x01, 0xF8]; // "CC" 0x01 0xF8
+
+// cc_type values carrying DTVCC (CEA-708) data
+const DTVCC_PACKET_DATA: u8 = 2;
+const DTVCC_PACKET_START: u8 = 3;
+
+const EXTENDED_SERVICE_NUMBER: u8 = 7;
+
 #[derive(Debug, Clone)]
 pub struct CaptionData {
     pub cc_valid: bool,
@@ -16,6 +27,7 @@ pub struct CaptionData {
     pub cc_data: [u8; 2],
 }
 
+#[derive(Default)]
 pub struct Cea708Parser;
 
 impl Cea708Parser {
@@ -39,14 +51,29 @@ impl Cea708Parser {
         }
         i += 1;
         
-        // Skip provider_code (2 bytes) and user_identifier (4 bytes)
-        if i + 6 > data.len() {
+        // Skip provider_code (2 bytes); user_identifier (4 bytes) and
+        // user_data_type_code must follow
+        if i + 7 > data.len() {
             debug!("Not enough data for provider code and identifier");
             return Ok(Vec::new());
         }
+        i += 2;
+        
+        self.parse_atsc_user_data(&data[i..])
+    }
+    
+    // Parses user data starting at the ATSC A/53 user_identifier, as carried
+    // in both T.35 SEI payloads and MPEG-2 picture user data
+    fn parse_atsc_user_data(&self, data: &[u8]) -> Result<Vec<CaptionData>> {
+        let mut i = 0;
+        
+        if data.len() < 5 {
+            debug!("ATSC user data too short: {} bytes", data.len());
+            return Ok(Vec::new());
+        }
         
         // Check for ATSC A/53 identifier "GA94"
-        if &data[i..i + 4] != &ATSC_IDENTIFIER {
+        if data[i..i + 4] != ATSC_IDENTIFIER {
             debug!("ATSC identifier mismatch. Expected: {:02x?}, Got: {:02x?}", 
                    &ATSC_IDENTIFIER, &data[i..i + 4]);
             return Ok(Vec::new());
@@ -117,4 +144,390 @@ impl Cea708Parser {
         
         Ok(captions)
     }
-}
\ No newline at end of file
+    
+    // Parses MPEG-2 picture user data (the bytes following the 0x000001B2
+    // start code). Besides ATSC A/53 this covers the older SCTE-20 and DVD
+    // caption formats, which only carry CEA-608 pairs.
+    pub fn parse_mpeg2_user_data(&self, data: &[u8]) -> Result<Vec<CaptionData>> {
+        if data.starts_with(&ATSC_IDENTIFIER) {
+            return self.parse_atsc_user_data(data);
+        }
+        
+        if data.starts_with(&DVD_IDENTIFIER) {
+            return Ok(self.parse_dvd_user_data(&data[DVD_IDENTIFIER.len()..]));
+        }
+        
+        if data.first() == Some(&SCTE20_USER_DATA_TYPE_CODE) {
+            return Ok(self.parse_scte20_user_data(&data[1..]));
+        }
+        
+        debug!("Unrecognized MPEG-2 user data: {:02x?}", &data[..data.len().min(4)]);
+        Ok(Vec::new())
+    }
+    
+    // DVD captions: a count byte followed by 3-byte entries, each a field
+    // marker (0xFF for field 1, 0xFE for field 2) and one CEA-608 pair
+    fn parse_dvd_user_data(&self, data: &[u8]) -> Vec<CaptionData> {
+        let Some(&flags) = data.first() else {
+            return Vec::new();
+        };
+        
+        let capture_count = ((flags & 0x1E) >> 1) as usize;
+        let truncated = (flags & 0x01) != 0;
+        let entry_count = capture_count * 2 + truncated as usize;
+        
+        let mut captions = Vec::new();
+        for entry in data[1..].chunks_exact(3).take(entry_count) {
+            let cc_type = match entry[0] {
+                0xFF => 0,
+                0xFE => 1,
+                marker => {
+                    debug!("Invalid DVD caption marker: 0x{:02x}", marker);
+                    continue;
+                }
+            };
+            
+            captions.push(CaptionData {
+                cc_valid: true,
+                cc_type,
+                cc_data: [entry[1], entry[2]],
+            });
+        }
+        
+        captions
+    }
+    
+    // SCTE-20 captions: a bit-packed list of CEA-608 pairs, each with its
+    // field number and the data bytes transmitted least significant bit first
+    fn parse_scte20_user_data(&self, data: &[u8]) -> Vec<CaptionData> {
+        let mut reader = BitReader::new(data);
+        let mut captions = Vec::new();
+        
+        if reader.read(1) != Some(1) {
+            debug!("SCTE-20 user data without VBI data");
+            return captions;
+        }
+        
+        let Some(cc_count) = reader.read(5) else {
+            return captions;
+        };
+        
+        for cc_index in 0..cc_count {
+            let (Some(_priority), Some(field_number), Some(_line_offset), Some(data1), Some(data2), Some(_marker)) = (
+                reader.read(2),
+                reader.read(2),
+                reader.read(5),
+                reader.read(8),
+                reader.read(8),
+                reader.read(1),
+            ) else {
+                debug!("Not enough data for SCTE-20 cc_data {}", cc_index);
+                break;
+            };
+            
+            // Field 1 may be repeated (3) for 3:2 pulldown; 0 is forbidden
+            let cc_type = match field_number {
+                1 | 3 => 0,
+                2 => 1,
+                _ => continue,
+            };
+            
+            captions.push(CaptionData {
+                cc_valid: true,
+                cc_type,
+                cc_data: [(data1 as u8).reverse_bits(), (data2 as u8).reverse_bits()],
+            });
+        }
+        
+        captions
+    }
+}
+
+// Reads big-endian bit fields from a byte slice
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+    
+    fn read(&mut self, bits: usize) -> Option<u32> {
+        if self.position + bits > self.data.len() * 8 {
+            return None;
+        }
+        
+        let mut value = 0u32;
+        for _ in 0..bits {
+            let byte = self.data[self.position / 8];
+            let bit = (byte >> (7 - self.position % 8)) & 0x01;
+            value = (value << 1) | bit as u32;
+            self.position += 1;
+        }
+        Some(value)
+    }
+}
+
+// A complete DTVCC caption channel packet, without its header byte
+#[derive(Debug, Clone)]
+pub struct DtvccPacket {
+    pub data: Vec<u8>,
+    pub timestamp: CaptionTime,
+}
+
+#[derive(Debug, Clone)]
+pub struct ServiceBlock {
+    pub service_number: u8,
+    pub data: Vec<u8>,
+    pub timestamp: CaptionTime,
+}
+
+// Reassembles DTVCC packets from DTVCC_PACKET_START/DATA cc_data pairs
+#[derive(Default)]
+pub struct DtvccPacketAssembler {
+    buffer: Vec<u8>,
+    packet_size: usize,
+    last_sequence_number: Option<u8>,
+}
+
+impl DtvccPacketAssembler {
+    pub fn new() -> Self {
+        Self {
+            buffer: Vec::new(),
+            packet_size: 0,
+            last_sequence_number: None,
+        }
+    }
+
+    pub fn push(&mut self, data: &CaptionData, timestamp: CaptionTime) -> Option<DtvccPacket> {
+        if !data.cc_valid {
+            return None;
+        }
+
+        match data.cc_type {
+            DTVCC_PACKET_START => {
+                // An unfinished packet is cut short by the next start
+                let previous = self.take_packet(timestamp);
+                if previous.is_some() {
+                    debug!("DTVCC packet truncated by new packet start");
+                }
+
+                let header = data.cc_data[0];
+                let sequence_number = header >> 6;
+                let size_code = (header & 0x3F) as usize;
+                self.packet_size = if size_code == 0 { 128 } else { size_code * 2 };
+
+                if let Some(last) = self.last_sequence_number {
+                    if sequence_number != (last + 1) % 4 {
+                        warn!("DTVCC sequence discontinuity: expected {}, got {}", (last + 1) % 4, sequence_number);
+                    }
+                }
+                self.last_sequence_number = Some(sequence_number);
+
+                // The packet size includes the header byte
+                self.buffer.clear();
+                self.buffer.push(data.cc_data[1]);
+                previous.or_else(|| self.complete_packet(timestamp))
+            }
+            DTVCC_PACKET_DATA => {
+                if self.packet_size == 0 {
+                    // Data without a preceding start cannot be placed
+                    return None;
+                }
+                self.buffer.extend_from_slice(&data.cc_data);
+                self.complete_packet(timestamp)
+            }
+            _ => None,
+        }
+    }
+
+    fn complete_packet(&mut self, timestamp: CaptionTime) -> Option<DtvccPacket> {
+        if self.packet_size > 0 && self.buffer.len() + 1 >= self.packet_size {
+            self.buffer.truncate(self.packet_size - 1);
+            return self.take_packet(timestamp);
+        }
+        None
+    }
+
+    fn take_packet(&mut self, timestamp: CaptionTime) -> Option<DtvccPacket> {
+        if self.packet_size == 0 {
+            return None;
+        }
+
+        self.packet_size = 0;
+        Some(DtvccPacket {
+            data: std::mem::take(&mut self.buffer),
+            timestamp,
+        })
+    }
+}
+
+pub fn parse_service_blocks(packet: &DtvccPacket) -> Vec<ServiceBlock> {
+    let data = &packet.data;
+    let mut blocks = Vec::new();
+    let mut i = 0;
+
+    while i < data.len() {
+        let mut service_number = data[i] >> 5;
+        let block_size = (data[i] & 0x1F) as usize;
+        i += 1;
+
+        // A null block header ends the packet; the rest is padding
+        if service_number == 0 {
+            break;
+        }
+
+        if service_number == EXTENDED_SERVICE_NUMBER {
+            if i >= data.len() {
+                break;
+            }
+            service_number = data[i] & 0x3F;
+            i += 1;
+        }
+
+        if i + block_size > data.len() {
+            debug!("Service block for service {} overruns DTVCC packet", service_number);
+            break;
+        }
+
+        if block_size > 0 {
+            blocks.push(ServiceBlock {
+                service_number,
+                data: data[i..i + block_size].to_vec(),
+                timestamp: packet.timestamp,
+            });
+        }
+
+        i += block_size;
+    }
+
+    blocks
+}
+
+// Splits the DTVCC channel into its caption services (1-63), keeping the
+// raw service bytes for each one.
+#[derive(Default)]
+pub struct DtvccDemuxer {
+    assembler: DtvccPacketAssembler,
+    service_data: BTreeMap<u8, Vec<u8>>,
+    services_seen: BTreeSet<u8>,
+}
+
+impl DtvccDemuxer {
+    pub fn new() -> Self {
+        Self {
+            assembler: DtvccPacketAssembler::new(),
+            service_data: BTreeMap::new(),
+            services_seen: BTreeSet::new(),
+        }
+    }
+
+    pub fn decode_caption_data(&mut self, caption_data: &[CaptionData], timestamp: CaptionTime) -> Vec<ServiceBlock> {
+        let mut blocks = Vec::new();
+
+        for data in caption_data {
+            if let Some(packet) = self.assembler.push(data, timestamp) {
+                for block in parse_service_blocks(&packet) {
+                    self.services_seen.insert(block.service_number);
+                    self.service_data
+                        .entry(block.service_number)
+                        .or_default()
+                        .extend_from_slice(&block.data);
+                    blocks.push(block);
+                }
+            }
+        }
+
+        blocks
+    }
+
+    // Raw bytes received for a service since the last call
+    pub fn take_service_data(&mut self, service_number: u8) -> Vec<u8> {
+        self.service_data
+            .get_mut(&service_number)
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    // Services that carried data since take_services_seen
+    pub fn services_seen(&self) -> impl Iterator<Item = u8> + '_ {
+        self.services_seen.iter().copied()
+    }
+
+    // Services that carried data since the last call
+    pub fn take_services_seen(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.services_seen).into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(frame: u64) -> CaptionTime {
+        CaptionTime {
+            pts: frame * 3003,
+            seconds: frame as f64 * 1001.0 / 30000.0,
+        }
+    }
+
+    fn dtvcc(cc_type: u8, data1: u8, data2: u8) -> CaptionData {
+        CaptionData {
+            cc_valid: true,
+            cc_type,
+            cc_data: [data1, data2],
+        }
+    }
+
+    #[test]
+    fn reassembles_packets_across_frames() {
+        let mut demuxer = DtvccDemuxer::new();
+        // Sequence 1, six bytes: header, service 1 block of three bytes, null padding
+        let first_frame = [dtvcc(DTVCC_PACKET_START, 0x43, 0x23), dtvcc(DTVCC_PACKET_DATA, b'A', b'B')];
+        assert!(demuxer.decode_caption_data(&first_frame, time(0)).is_empty());
+
+        let second_frame = [dtvcc(DTVCC_PACKET_DATA, b'C', 0x00)];
+        let blocks = demuxer.decode_caption_data(&second_frame, time(1));
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].service_number, 1);
+        assert_eq!(blocks[0].data, b"ABC");
+        assert_eq!(blocks[0].timestamp, time(1));
+        assert_eq!(demuxer.take_service_data(1), b"ABC");
+    }
+
+    #[test]
+    fn reads_extended_service_numbers() {
+        let mut demuxer = DtvccDemuxer::new();
+        // Service 7 in the block header points to the extended service number
+        // byte, here 42
+        let frame = [
+            dtvcc(DTVCC_PACKET_START, 0x03, 0xE2),
+            dtvcc(DTVCC_PACKET_DATA, 0x2A, b'X'),
+            dtvcc(DTVCC_PACKET_DATA, b'Y', 0x00),
+        ];
+        let blocks = demuxer.decode_caption_data(&frame, time(0));
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].service_number, 42);
+        assert_eq!(blocks[0].data, b"XY");
+        assert_eq!(demuxer.take_services_seen(), vec![42]);
+    }
+
+    #[test]
+    fn keeps_packets_after_sequence_discontinuity() {
+        let mut demuxer = DtvccDemuxer::new();
+        // Four-byte packets with sequence numbers 0 and 2, each holding a
+        // one-byte service 1 block
+        let frame = [
+            dtvcc(DTVCC_PACKET_START, 0x02, 0x21),
+            dtvcc(DTVCC_PACKET_DATA, b'A', 0x00),
+            dtvcc(DTVCC_PACKET_START, 0x82, 0x21),
+            dtvcc(DTVCC_PACKET_DATA, b'B', 0x00),
+        ];
+        let blocks = demuxer.decode_caption_data(&frame, time(0));
+        let data: Vec<&[u8]> = blocks.iter().map(|block| block.data.as_slice()).collect();
+        assert_eq!(data, [b"A", b"B"]);
+        assert_eq!(demuxer.take_service_data(1), b"AB");
+    }
+}
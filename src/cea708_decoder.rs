@@ -0,0 +1,700 @@
+use log::debug;
+use crate::caption_event::{CaptionCell, CaptionChannel, CaptionColor, CaptionEvent, CaptionLine, CaptionStyle, CaptionTime};
+use crate::cea608::{SCREEN_COLUMNS, SCREEN_ROWS};
+use crate::cea708::ServiceBlock;
+
+const WINDOW_COUNT: usize = 8;
+const MAX_WINDOW_ROWS: usize = 15;
+const MAX_WINDOW_COLUMNS: usize = 42;
+
+// C0 control codes
+const NUL: u8 = 0x00;
+const ETX: u8 = 0x03;
+const BS: u8 = 0x08;
+const FF: u8 = 0x0C;
+const CR: u8 = 0x0D;
+const HCR: u8 = 0x0E;
+const EXT1: u8 = 0x10;
+const P16: u8 = 0x18;
+
+// C1 caption commands
+const CW0: u8 = 0x80;
+const CW7: u8 = 0x87;
+const CLW: u8 = 0x88;
+const DSW: u8 = 0x89;
+const HDW: u8 = 0x8A;
+const TGW: u8 = 0x8B;
+const DLW: u8 = 0x8C;
+const DLY: u8 = 0x8D;
+const DLC: u8 = 0x8E;
+const RST: u8 = 0x8F;
+const SPA: u8 = 0x90;
+const SPC: u8 = 0x91;
+const SPL: u8 = 0x92;
+const SWA: u8 = 0x97;
+const DF0: u8 = 0x98;
+const DF7: u8 = 0x9F;
+
+// Anchor coordinates are expressed on a 75 x 210 grid (16:9) unless the
+// window uses relative positioning, in which case they are percentages
+const ANCHOR_VERTICAL_MAX: usize = 75;
+const ANCHOR_HORIZONTAL_MAX: usize = 210;
+
+#[derive(Debug, Clone)]
+struct Window {
+    defined: bool,
+    visible: bool,
+    priority: u8,
+    relative_positioning: bool,
+    anchor_vertical: u8,
+    anchor_horizontal: u8,
+    anchor_point: u8,
+    row_count: usize,
+    column_count: usize,
+    word_wrap: bool,
+    rows: Vec<Vec<Option<CaptionCell>>>,
+    pen_row: usize,
+    pen_column: usize,
+    pen: CaptionStyle,
+}
+
+impl Window {
+    fn new() -> Self {
+        Self {
+            defined: false,
+            visible: false,
+            priority: 0,
+            relative_positioning: false,
+            anchor_vertical: 0,
+            anchor_horizontal: 0,
+            anchor_point: 0,
+            row_count: 1,
+            column_count: SCREEN_COLUMNS,
+            word_wrap: false,
+            rows: vec![vec![None; MAX_WINDOW_COLUMNS]; MAX_WINDOW_ROWS],
+            pen_row: 0,
+            pen_column: 0,
+            pen: CaptionStyle::default(),
+        }
+    }
+
+    fn clear(&mut self) {
+        for row in &mut self.rows {
+            row.iter_mut().for_each(|cell| *cell = None);
+        }
+        self.pen_row = 0;
+        self.pen_column = 0;
+    }
+
+    fn write_char(&mut self, ch: char) {
+        if self.pen_column >= self.column_count {
+            self.carriage_return();
+        }
+        let pen = self.pen;
+        self.rows[self.pen_row][self.pen_column] = Some(CaptionCell { ch, style: pen });
+        self.pen_column += 1;
+    }
+
+    fn backspace(&mut self) {
+        if self.pen_column > 0 {
+            self.pen_column -= 1;
+            self.rows[self.pen_row][self.pen_column] = None;
+        }
+    }
+
+    fn carriage_return(&mut self) {
+        self.pen_column = 0;
+        if self.pen_row + 1 < self.row_count {
+            self.pen_row += 1;
+        } else {
+            // Scroll the window contents up by one row
+            self.rows[..self.row_count].rotate_left(1);
+            self.rows[self.row_count - 1].iter_mut().for_each(|cell| *cell = None);
+        }
+    }
+
+    fn horizontal_carriage_return(&mut self) {
+        self.rows[self.pen_row].iter_mut().for_each(|cell| *cell = None);
+        self.pen_column = 0;
+    }
+
+    // Top-left position of the window on the 15x32 caption grid
+    fn grid_origin(&self) -> (usize, usize) {
+        let (vertical_max, horizontal_max) = if self.relative_positioning {
+            (100, 100)
+        } else {
+            (ANCHOR_VERTICAL_MAX, ANCHOR_HORIZONTAL_MAX)
+        };
+
+        let anchor_row = self.anchor_vertical as usize * SCREEN_ROWS / vertical_max;
+        let anchor_column = self.anchor_horizontal as usize * SCREEN_COLUMNS / horizontal_max;
+
+        // Anchor points 0-8 run left to right, top to bottom
+        let row = match self.anchor_point / 3 {
+            0 => anchor_row,
+            1 => anchor_row.saturating_sub(self.row_count / 2),
+            _ => (anchor_row + 1).saturating_sub(self.row_count),
+        };
+        let column = match self.anchor_point % 3 {
+            0 => anchor_column,
+            1 => anchor_column.saturating_sub(self.column_count / 2),
+            _ => (anchor_column + 1).saturating_sub(self.column_count),
+        };
+
+        (row.min(SCREEN_ROWS - 1), column.min(SCREEN_COLUMNS - 1))
+    }
+
+    fn lines(&self) -> Vec<CaptionLine> {
+        let (origin_row, origin_column) = self.grid_origin();
+        let mut lines = Vec::new();
+
+        for (index, cells) in self.rows[..self.row_count].iter().enumerate() {
+            let Some(mut line) = CaptionLine::from_cells(origin_row + index, origin_column, &cells[..self.column_count]) else {
+                continue;
+            };
+            line.row = line.row.min(SCREEN_ROWS - 1);
+            line.column = line.column.min(SCREEN_COLUMNS - 1);
+            if !line.text().trim().is_empty() {
+                lines.push(line);
+            }
+        }
+
+        lines
+    }
+}
+
+// Interprets one CEA-708 caption service: the eight windows, pen state and
+// the C0-C3/G0-G3 code sets. A caption is emitted whenever the visible text
+// is removed or replaced, covering the time it was on screen.
+pub struct Cea708ServiceDecoder {
+    service_number: u8,
+    windows: Vec<Window>,
+    current_window: Option<usize>,
+    shown_text: String,
+    shown_lines: Vec<CaptionLine>,
+    shown_since: Option<CaptionTime>,
+}
+
+impl Cea708ServiceDecoder {
+    pub fn new(service_number: u8) -> Self {
+        Self {
+            service_number,
+            windows: vec![Window::new(); WINDOW_COUNT],
+            current_window: None,
+            shown_text: String::new(),
+            shown_lines: Vec::new(),
+            shown_since: None,
+        }
+    }
+
+    pub fn decode_service_block(&mut self, block: &ServiceBlock) -> Vec<CaptionEvent> {
+        let mut captions = Vec::new();
+        let data = &block.data;
+        let mut i = 0;
+
+        while i < data.len() {
+            let code = data[i];
+            i += 1;
+
+            let consumed = match code {
+                EXT1 => self.decode_extended(&data[i..]),
+                0x00..=0x1F => self.decode_c0(code, &data[i..]),
+                0x20..=0x7F => {
+                    self.write_char(g0_char(code));
+                    Some(0)
+                }
+                0x80..=0x9F => self.decode_c1(code, &data[i..], block.timestamp, &mut captions),
+                0xA0..=0xFF => {
+                    self.write_char(code as char);
+                    Some(0)
+                }
+            };
+
+            match consumed {
+                Some(length) => i += length,
+                None => {
+                    debug!("Truncated CEA-708 command 0x{:02x} in service {}", code, self.service_number);
+                    break;
+                }
+            }
+        }
+
+        captions.extend(self.update_shown(block.timestamp));
+        captions
+    }
+
+    pub fn flush(&mut self, timestamp: CaptionTime) -> Option<CaptionEvent> {
+        let caption = self.emit_shown(timestamp);
+        self.shown_text.clear();
+        self.shown_lines.clear();
+        caption
+    }
+
+    fn decode_c0(&mut self, code: u8, params: &[u8]) -> Option<usize> {
+        match code {
+            NUL | ETX => {}
+            BS => self.with_current_window(Window::backspace),
+            FF => self.with_current_window(Window::clear),
+            CR => self.with_current_window(Window::carriage_return),
+            HCR => self.with_current_window(Window::horizontal_carriage_return),
+            P16 => {
+                // 16-bit character codes are outside the supported sets
+                if params.len() < 2 {
+                    return None;
+                }
+                self.write_char('_');
+                return Some(2);
+            }
+            _ => {}
+        }
+
+        // Unused C0 codes still carry parameter bytes by range
+        let length = match code {
+            0x11..=0x17 => 1,
+            0x19..=0x1F => 2,
+            _ => 0,
+        };
+        if params.len() < length {
+            return None;
+        }
+        Some(length)
+    }
+
+    fn decode_extended(&mut self, data: &[u8]) -> Option<usize> {
+        let code = *data.first()?;
+        let params = &data[1..];
+
+        let length = match code {
+            // C2: reserved codes with 0-3 parameter bytes
+            0x00..=0x07 => 0,
+            0x08..=0x0F => 1,
+            0x10..=0x17 => 2,
+            0x18..=0x1F => 3,
+            0x20..=0x7F => {
+                self.write_char(g2_char(code));
+                0
+            }
+            // C3: reserved codes with fixed or variable length
+            0x80..=0x87 => 4,
+            0x88..=0x8F => 5,
+            0x90..=0x9F => 1 + (*params.first()? & 0x3F) as usize,
+            0xA0..=0xFF => {
+                g3_text(code).chars().for_each(|ch| self.write_char(ch));
+                0
+            }
+        };
+
+        if params.len() < length {
+            return None;
+        }
+        Some(1 + length)
+    }
+
+    fn decode_c1(&mut self, code: u8, params: &[u8], timestamp: CaptionTime, captions: &mut Vec<CaptionEvent>) -> Option<usize> {
+        let length = match code {
+            CW0..=CW7 => 0,
+            CLW | DSW | HDW | TGW | DLW | DLY => 1,
+            DLC | RST => 0,
+            SPA | SPL => 2,
+            SPC => 3,
+            SWA => 4,
+            DF0..=DF7 => 6,
+            _ => 0,
+        };
+
+        if params.len() < length {
+            return None;
+        }
+        let params = &params[..length];
+
+        match code {
+            CW0..=CW7 => {
+                let window = (code - CW0) as usize;
+                if self.windows[window].defined {
+                    self.current_window = Some(window);
+                }
+            }
+            CLW => self.for_windows(params[0], Window::clear),
+            DSW => self.for_windows(params[0], |window| window.visible = true),
+            HDW => self.for_windows(params[0], |window| window.visible = false),
+            TGW => self.for_windows(params[0], |window| window.visible = !window.visible),
+            DLW => {
+                if let Some(current) = self.current_window {
+                    if params[0] & (1 << current) != 0 {
+                        self.current_window = None;
+                    }
+                }
+                self.for_windows(params[0], |window| *window = Window::new());
+            }
+            // Delays are not modelled; commands take effect immediately
+            DLY | DLC => {}
+            RST => {
+                captions.extend(self.update_shown(timestamp));
+                self.windows = vec![Window::new(); WINDOW_COUNT];
+                self.current_window = None;
+            }
+            SPA => self.with_current_window(|window| {
+                window.pen.italics = params[1] & 0x80 != 0;
+                window.pen.underline = params[1] & 0x40 != 0;
+            }),
+            SPC => self.with_current_window(|window| {
+                let color = params[0];
+                window.pen.color = CaptionColor::from_rgb2((color >> 4) & 0x03, (color >> 2) & 0x03, color & 0x03);
+            }),
+            SPL => self.with_current_window(|window| {
+                window.pen_row = ((params[0] & 0x0F) as usize).min(window.row_count - 1);
+                window.pen_column = ((params[1] & 0x3F) as usize).min(window.column_count - 1);
+            }),
+            SWA => self.with_current_window(|window| {
+                window.word_wrap = params[2] & 0x40 != 0;
+            }),
+            DF0..=DF7 => self.define_window((code - DF0) as usize, params),
+            _ => debug!("Ignoring reserved CEA-708 command 0x{:02x}", code),
+        }
+
+        // Window visibility and content changes end the caption on screen
+        if matches!(code, CLW | HDW | TGW | DLW | DF0..=DF7) {
+            captions.extend(self.update_shown(timestamp));
+        }
+
+        Some(length)
+    }
+
+    fn define_window(&mut self, index: usize, params: &[u8]) {
+        let window = &mut self.windows[index];
+
+        // Redefining an existing window keeps its contents
+        if !window.defined {
+            *window = Window::new();
+            window.defined = true;
+        }
+
+        window.visible = params[0] & 0x20 != 0;
+        window.priority = params[0] & 0x07;
+        window.relative_positioning = params[1] & 0x80 != 0;
+        window.anchor_vertical = params[1] & 0x7F;
+        window.anchor_horizontal = params[2];
+        window.anchor_point = (params[3] >> 4).min(8);
+        window.row_count = ((params[3] & 0x0F) as usize + 1).min(MAX_WINDOW_ROWS);
+        window.column_count = ((params[4] & 0x3F) as usize + 1).min(MAX_WINDOW_COLUMNS);
+        window.pen_row = window.pen_row.min(window.row_count - 1);
+        window.pen_column = window.pen_column.min(window.column_count);
+
+        self.current_window = Some(index);
+    }
+
+    fn for_windows(&mut self, bitmap: u8, mut action: impl FnMut(&mut Window)) {
+        for (index, window) in self.windows.iter_mut().enumerate() {
+            if bitmap & (1 << index) != 0 && window.defined {
+                action(window);
+            }
+        }
+    }
+
+    fn with_current_window(&mut self, action: impl FnOnce(&mut Window)) {
+        if let Some(index) = self.current_window {
+            action(&mut self.windows[index]);
+        }
+    }
+
+    fn write_char(&mut self, ch: char) {
+        self.with_current_window(|window| window.write_char(ch));
+    }
+
+    fn visible_lines(&self) -> Vec<CaptionLine> {
+        let mut windows: Vec<_> = self.windows.iter().filter(|window| window.defined && window.visible).collect();
+        windows.sort_by_key(|window| window.priority);
+
+        let mut lines: Vec<_> = windows.iter().flat_map(|window| window.lines()).collect();
+        lines.sort_by_key(|line| (line.row, line.column));
+        lines
+    }
+
+    // Compares the visible text with what was shown before. Text that only
+    // grew is still the same caption; anything else ends it.
+    fn update_shown(&mut self, timestamp: CaptionTime) -> Option<CaptionEvent> {
+        let lines = self.visible_lines();
+        let text = lines_text(&lines);
+
+        if text == self.shown_text {
+            return None;
+        }
+
+        let mut caption = None;
+        if self.shown_text.is_empty() || !text.starts_with(&self.shown_text) {
+            caption = self.emit_shown(timestamp);
+            self.shown_since = if text.is_empty() { None } else { Some(timestamp) };
+        }
+
+        self.shown_text = text;
+        self.shown_lines = lines;
+        caption
+    }
+
+    fn emit_shown(&mut self, timestamp: CaptionTime) -> Option<CaptionEvent> {
+        let start = self.shown_since.take()?;
+        if self.shown_text.is_empty() {
+            return None;
+        }
+
+        Some(CaptionEvent {
+            channel: CaptionChannel::Service(self.service_number),
+            text: self.shown_text.clone(),
+            start,
+            end: timestamp.max(start),
+            lines: self.shown_lines.clone(),
+        })
+    }
+}
+
+fn lines_text(lines: &[CaptionLine]) -> String {
+    lines
+        .iter()
+        .map(|line| line.text().trim().to_string())
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// G0 is ASCII except for the music note
+fn g0_char(code: u8) -> char {
+    if code == 0x7F {
+        '♪'
+    } else {
+        code as char
+    }
+}
+
+fn g2_char(code: u8) -> char {
+    match code {
+        0x20 => ' ', // Transparent space
+        0x21 => '\u{a0}', // Non-breaking transparent space
+        0x25 => '…',
+        0x2A => 'Š',
+        0x2C => 'Œ',
+        0x30 => '█',
+        0x31 => '‘',
+        0x32 => '’',
+        0x33 => '“',
+        0x34 => '”',
+        0x35 => '•',
+        0x39 => '™',
+        0x3A => 'š',
+        0x3C => 'œ',
+        0x3D => '℠',
+        0x3F => 'Ÿ',
+        0x76 => '⅛',
+        0x77 => '⅜',
+        0x78 => '⅝',
+        0x79 => '⅞',
+        0x7A => '│',
+        0x7B => '┐',
+        0x7C => '└',
+        0x7D => '─',
+        0x7E => '┘',
+        0x7F => '┌',
+        _ => '_',
+    }
+}
+
+// G3 only defines the closed caption icon, which has no Unicode character,
+// so it is written out as the "[CC]" mark used in caption text
+fn g3_text(code: u8) -> &'static str {
+    match code {
+        0xA0 => "[CC]",
+        _ => "_",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(frame: u64) -> CaptionTime {
+        CaptionTime {
+            pts: frame * 3003,
+            seconds: frame as f64 * 1001.0 / 30000.0,
+        }
+    }
+
+    fn decode(decoder: &mut Cea708ServiceDecoder, frame: u64, data: &[u8]) -> Vec<CaptionEvent> {
+        decoder.decode_service_block(&ServiceBlock {
+            service_number: 1,
+            data: data.to_vec(),
+            timestamp: time(frame),
+        })
+    }
+
+    // DefineWindow with the top-left anchor point and absolute positioning
+    fn define_window(index: u8, visible: bool, anchor_vertical: u8, anchor_horizontal: u8, rows: u8, columns: u8) -> Vec<u8> {
+        vec![
+            DF0 + index,
+            if visible { 0x20 } else { 0x00 },
+            anchor_vertical,
+            anchor_horizontal,
+            rows - 1,
+            columns - 1,
+            0x00,
+        ]
+    }
+
+    fn with_text(mut data: Vec<u8>, text: &str) -> Vec<u8> {
+        data.extend_from_slice(text.as_bytes());
+        data
+    }
+
+    #[test]
+    fn visible_window_shows_until_cleared() {
+        let mut decoder = Cea708ServiceDecoder::new(1);
+        assert!(decode(&mut decoder, 0, &with_text(define_window(0, true, 60, 0, 1, 32), "HELLO")).is_empty());
+
+        let captions = decode(&mut decoder, 10, &[CLW, 0x01]);
+
+        assert_eq!(captions.len(), 1);
+        let caption = &captions[0];
+        assert_eq!(caption.channel, CaptionChannel::Service(1));
+        assert_eq!(caption.text, "HELLO");
+        assert_eq!(caption.start, time(0));
+        assert_eq!(caption.end, time(10));
+        assert_eq!(caption.lines.len(), 1);
+        assert_eq!((caption.lines[0].row, caption.lines[0].column), (12, 0));
+
+        // The cleared window stays defined and empty
+        assert!(decode(&mut decoder, 20, &[]).is_empty());
+        assert!(decoder.flush(time(30)).is_none());
+    }
+
+    #[test]
+    fn hidden_window_shows_from_dsw_until_hdw() {
+        let mut decoder = Cea708ServiceDecoder::new(1);
+        assert!(decode(&mut decoder, 0, &with_text(define_window(0, false, 60, 0, 1, 32), "HELLO")).is_empty());
+        assert!(decode(&mut decoder, 5, &[DSW, 0x01]).is_empty());
+
+        let captions = decode(&mut decoder, 15, &[HDW, 0x01]);
+
+        assert_eq!(captions.len(), 1);
+        assert_eq!(captions[0].text, "HELLO");
+        assert_eq!(captions[0].start, time(5));
+        assert_eq!(captions[0].end, time(15));
+    }
+
+    #[test]
+    fn toggled_windows_show_together_until_one_is_deleted() {
+        let mut decoder = Cea708ServiceDecoder::new(1);
+        let mut data = with_text(define_window(0, false, 60, 0, 1, 32), "BOTTOM");
+        data.extend(with_text(define_window(1, false, 15, 105, 1, 16), "TOP"));
+        assert!(decode(&mut decoder, 0, &data).is_empty());
+        assert!(decode(&mut decoder, 5, &[TGW, 0x03]).is_empty());
+
+        let captions = decode(&mut decoder, 10, &[DLW, 0x01]);
+
+        assert_eq!(captions.len(), 1);
+        let caption = &captions[0];
+        assert_eq!(caption.text, "TOP\nBOTTOM");
+        assert_eq!(caption.start, time(5));
+        assert_eq!(caption.end, time(10));
+        let positions: Vec<_> = caption.lines.iter().map(|line| (line.row, line.column)).collect();
+        assert_eq!(positions, [(3, 16), (12, 0)]);
+
+        // The remaining window is a new caption from the deletion on
+        let captions = decode(&mut decoder, 20, &[TGW, 0x02]);
+        assert_eq!(captions.len(), 1);
+        assert_eq!(captions[0].text, "TOP");
+        assert_eq!(captions[0].start, time(10));
+        assert_eq!(captions[0].end, time(20));
+    }
+
+    #[test]
+    fn deleted_window_is_no_longer_current() {
+        let mut decoder = Cea708ServiceDecoder::new(1);
+        assert!(decode(&mut decoder, 0, &define_window(0, true, 60, 0, 1, 32)).is_empty());
+
+        let mut data = vec![DLW, 0x01];
+        data.extend_from_slice(b"LOST");
+        assert!(decode(&mut decoder, 5, &data).is_empty());
+        assert!(decoder.flush(time(10)).is_none());
+    }
+
+    #[test]
+    fn pen_attributes_and_location_apply_to_following_text() {
+        let mut decoder = Cea708ServiceDecoder::new(1);
+        let mut data = define_window(0, true, 0, 0, 2, 10);
+        data.extend([SPA, 0x00, 0x80, SPC, 0x30, 0x00, 0x00]);
+        data.extend_from_slice(b"AB");
+        data.extend([SPA, 0x00, 0x40, SPC, 0x2A, 0x00, 0x00, SPL, 0x01, 0x04]);
+        data.extend_from_slice(b"C");
+        assert!(decode(&mut decoder, 0, &data).is_empty());
+
+        let caption = decoder.flush(time(10)).unwrap();
+
+        assert_eq!(caption.text, "AB\nC");
+        assert_eq!(caption.lines.len(), 2);
+        let first = &caption.lines[0];
+        assert_eq!((first.row, first.column), (0, 0));
+        assert_eq!(first.spans.len(), 1);
+        assert_eq!(
+            first.spans[0].style,
+            CaptionStyle {
+                color: CaptionColor::Red,
+                italics: true,
+                underline: false,
+            }
+        );
+        let second = &caption.lines[1];
+        assert_eq!((second.row, second.column), (1, 4));
+        assert_eq!(
+            second.spans[0].style,
+            CaptionStyle {
+                color: CaptionColor::White,
+                italics: false,
+                underline: true,
+            }
+        );
+    }
+
+    #[test]
+    fn g2_and_g3_characters_are_mapped() {
+        let mut decoder = Cea708ServiceDecoder::new(1);
+        let mut data = define_window(0, true, 60, 0, 1, 32);
+        data.extend([EXT1, 0x25, EXT1, 0x39, 0x7F, EXT1, 0xA0, EXT1, 0x60]);
+        assert!(decode(&mut decoder, 0, &data).is_empty());
+
+        let caption = decoder.flush(time(10)).unwrap();
+        assert_eq!(caption.text, "…™♪[CC]_");
+    }
+
+    #[test]
+    fn truncated_command_ends_the_block() {
+        let mut decoder = Cea708ServiceDecoder::new(1);
+        let mut data = with_text(define_window(0, true, 60, 0, 1, 32), "HI");
+        data.extend([SPL, 0x00]);
+        assert!(decode(&mut decoder, 0, &data).is_empty());
+
+        // A truncated extended code is dropped the same way
+        assert!(decode(&mut decoder, 5, &[b'!', EXT1]).is_empty());
+        assert!(decode(&mut decoder, 6, &[EXT1, 0x90]).is_empty());
+
+        let caption = decoder.flush(time(10)).unwrap();
+        assert_eq!(caption.text, "HI!");
+        assert_eq!(caption.start, time(0));
+    }
+
+    #[test]
+    fn growing_text_continues_the_caption() {
+        let mut decoder = Cea708ServiceDecoder::new(1);
+        assert!(decode(&mut decoder, 0, &with_text(define_window(0, true, 60, 0, 1, 32), "HEL")).is_empty());
+        assert!(decode(&mut decoder, 5, b"LO").is_empty());
+
+        // Anything other than appended text replaces the caption
+        let captions = decode(&mut decoder, 8, &[BS]);
+        assert_eq!(captions.len(), 1);
+        assert_eq!(captions[0].text, "HELLO");
+        assert_eq!(captions[0].start, time(0));
+        assert_eq!(captions[0].end, time(8));
+
+        let caption = decoder.flush(time(10)).unwrap();
+        assert_eq!(caption.text, "HELL");
+        assert_eq!(caption.start, time(8));
+        assert_eq!(caption.end, time(10));
+    }
+}
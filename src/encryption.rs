@@ -0,0 +1,67 @@
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+use anyhow::{Result, anyhow};
+
+type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+
+// Decrypts a whole segment encrypted with METHOD=AES-128 (AES-128-CBC with
+// PKCS7 padding)
+pub fn decrypt_aes128(key: &[u8; 16], iv: &[u8; 16], data: &[u8]) -> Result<Vec<u8>> {
+    if !data.len().is_multiple_of(16) {
+        return Err(anyhow!("Encrypted segment size {} is not a multiple of the AES block size", data.len()));
+    }
+    
+    let mut buffer = data.to_vec();
+    let length = Aes128CbcDec::new(key.into(), iv.into())
+        .decrypt_padded_mut::<Pkcs7>(&mut buffer)
+        .map_err(|_| anyhow!("Invalid PKCS7 padding after AES-128 decryption (wrong key or IV?)"))?
+        .len();
+    buffer.truncate(length);
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // NIST SP 800-38A F.2.1 (CBC-AES128), first block
+    const KEY: [u8; 16] = [
+        0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f, 0x3c,
+    ];
+    const IV: [u8; 16] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+    ];
+    const PLAINTEXT: [u8; 16] = [
+        0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17, 0x2a,
+    ];
+    // The NIST block followed by the full block of PKCS7 padding
+    const CIPHERTEXT: [u8; 32] = [
+        0x76, 0x49, 0xab, 0xac, 0x81, 0x19, 0xb2, 0x46, 0xce, 0xe9, 0x8e, 0x9b, 0x12, 0xe9, 0x19, 0x7d,
+        0x89, 0x64, 0xe0, 0xb1, 0x49, 0xc1, 0x0b, 0x7b, 0x68, 0x2e, 0x6e, 0x39, 0xaa, 0xeb, 0x73, 0x1c,
+    ];
+
+    #[test]
+    fn decrypts_cbc_and_removes_padding() {
+        assert_eq!(decrypt_aes128(&KEY, &IV, &CIPHERTEXT).unwrap(), PLAINTEXT);
+    }
+
+    #[test]
+    fn removes_partial_block_padding() {
+        // "caption" under the IV of media sequence number 7
+        let ciphertext = [
+            0x4a, 0xa7, 0x60, 0x0b, 0xb2, 0x1c, 0x8c, 0x7a, 0x19, 0x63, 0xb0, 0x93, 0x8d, 0xb7, 0x98, 0x0d,
+        ];
+        let iv = 7u128.to_be_bytes();
+        assert_eq!(decrypt_aes128(&KEY, &iv, &ciphertext).unwrap(), b"caption");
+    }
+
+    #[test]
+    fn rejects_partial_blocks() {
+        assert!(decrypt_aes128(&KEY, &IV, &CIPHERTEXT[..20]).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_padding() {
+        // Without its padding block the data ends in plaintext bytes
+        assert!(decrypt_aes128(&KEY, &IV, &CIPHERTEXT[..16]).is_err());
+    }
+}
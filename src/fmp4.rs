@@ -0,0 +1,413 @@
+use anyhow::{Result, anyhow};
+use log::debug;
+use crate::nal::VideoCodec;
+
+// Flags of the track fragment header (tfhd)
+const TFHD_BASE_DATA_OFFSET: u32 = 0x000001;
+const TFHD_SAMPLE_DESCRIPTION_INDEX: u32 = 0x000002;
+const TFHD_DEFAULT_SAMPLE_DURATION: u32 = 0x000008;
+const TFHD_DEFAULT_SAMPLE_SIZE: u32 = 0x000010;
+
+// Flags of the track fragment run (trun)
+const TRUN_DATA_OFFSET: u32 = 0x000001;
+const TRUN_FIRST_SAMPLE_FLAGS: u32 = 0x000004;
+const TRUN_SAMPLE_DURATION: u32 = 0x000100;
+const TRUN_SAMPLE_SIZE: u32 = 0x000200;
+const TRUN_SAMPLE_FLAGS: u32 = 0x000400;
+const TRUN_SAMPLE_COMPOSITION_OFFSET: u32 = 0x000800;
+
+// Size of a VisualSampleEntry before its child boxes
+const VISUAL_SAMPLE_ENTRY_SIZE: usize = 78;
+
+// An ISO BMFF box: its four character type, payload and where the box and
+// its payload start within the buffer it was read from
+struct Mp4Box<'a> {
+    box_type: [u8; 4],
+    offset: usize,
+    payload_offset: usize,
+    payload: &'a [u8],
+}
+
+fn parse_boxes(data: &[u8]) -> Vec<Mp4Box<'_>> {
+    let mut boxes = Vec::new();
+    let mut i = 0;
+    
+    while i + 8 <= data.len() {
+        let size = u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]) as u64;
+        let box_type = [data[i + 4], data[i + 5], data[i + 6], data[i + 7]];
+        
+        let (header_size, size) = match size {
+            // Box extends to the end of the buffer
+            0 => (8, (data.len() - i) as u64),
+            // 64-bit largesize follows the type
+            1 => {
+                if i + 16 > data.len() {
+                    break;
+                }
+                (16, u64::from_be_bytes(data[i + 8..i + 16].try_into().unwrap()))
+            }
+            size => (8, size),
+        };
+        
+        if size < header_size as u64 || i as u64 + size > data.len() as u64 {
+            debug!("Truncated {} box at offset {}", String::from_utf8_lossy(&box_type), i);
+            break;
+        }
+        
+        let end = i + size as usize;
+        boxes.push(Mp4Box {
+            box_type,
+            offset: i,
+            payload_offset: i + header_size,
+            payload: &data[i + header_size..end],
+        });
+        i = end;
+    }
+    
+    boxes
+}
+
+fn find_box<'a>(data: &'a [u8], box_type: &[u8; 4]) -> Option<&'a [u8]> {
+    parse_boxes(data).into_iter().find(|b| &b.box_type == box_type).map(|b| b.payload)
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32> {
+    data.get(pos..pos + 4)
+        .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or_else(|| anyhow!("Truncated box reading 4 bytes at offset {}", pos))
+}
+
+fn read_u64(data: &[u8], pos: usize) -> Result<u64> {
+    data.get(pos..pos + 8)
+        .map(|bytes| u64::from_be_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| anyhow!("Truncated box reading 8 bytes at offset {}", pos))
+}
+
+// The video track described by an init segment (moov)
+#[derive(Debug, Clone)]
+pub struct Fmp4Track {
+    pub track_id: u32,
+    pub timescale: u32,
+    pub codec: VideoCodec,
+    pub nal_length_size: usize,
+    default_sample_duration: u32,
+    default_sample_size: u32,
+}
+
+// One video sample (access unit) of a media segment, with its presentation
+// time converted to the 90 kHz MPEG clock
+pub struct Fmp4Sample<'a> {
+    pub pts: u64,
+    pub data: &'a [u8],
+}
+
+pub fn parse_init_segment(data: &[u8]) -> Result<Fmp4Track> {
+    let moov = find_box(data, b"moov").ok_or_else(|| anyhow!("Init segment has no moov box"))?;
+    
+    for trak in parse_boxes(moov).into_iter().filter(|b| &b.box_type == b"trak") {
+        if let Some(mut track) = parse_video_track(trak.payload)? {
+            // Fragment defaults from the movie extends box
+            if let Some(mvex) = find_box(moov, b"mvex") {
+                for trex in parse_boxes(mvex).into_iter().filter(|b| &b.box_type == b"trex") {
+                    if read_u32(trex.payload, 4)? == track.track_id {
+                        track.default_sample_duration = read_u32(trex.payload, 12)?;
+                        track.default_sample_size = read_u32(trex.payload, 16)?;
+                    }
+                }
+            }
+            
+            debug!("fMP4 video track {}: {:?}, timescale {}, NAL length size {}",
+                   track.track_id, track.codec, track.timescale, track.nal_length_size);
+            return Ok(track);
+        }
+    }
+    
+    Err(anyhow!("Init segment has no H.264 or HEVC video track"))
+}
+
+fn parse_video_track(trak: &[u8]) -> Result<Option<Fmp4Track>> {
+    let tkhd = find_box(trak, b"tkhd").ok_or_else(|| anyhow!("Track has no tkhd box"))?;
+    let mdia = find_box(trak, b"mdia").ok_or_else(|| anyhow!("Track has no mdia box"))?;
+    
+    let handler = find_box(mdia, b"hdlr").ok_or_else(|| anyhow!("Track has no hdlr box"))?;
+    if handler.get(8..12) != Some(b"vide") {
+        return Ok(None);
+    }
+
+    // tkhd and mdhd fields move with the version (64-bit times in version 1)
+    let track_id = if tkhd.first() == Some(&1) { read_u32(tkhd, 20)? } else { read_u32(tkhd, 12)? };
+    
+    let mdhd = find_box(mdia, b"mdhd").ok_or_else(|| anyhow!("Track has no mdhd box"))?;
+    let timescale = if mdhd.first() == Some(&1) { read_u32(mdhd, 20)? } else { read_u32(mdhd, 12)? };
+    
+    let stsd = find_box(mdia, b"minf")
+        .and_then(|minf| find_box(minf, b"stbl"))
+        .and_then(|stbl| find_box(stbl, b"stsd"))
+        .ok_or_else(|| anyhow!("Video track has no stsd box"))?;
+    
+    // Skip version/flags and entry_count to the sample entries
+    for entry in parse_boxes(stsd.get(8..).unwrap_or_default()) {
+        let children = entry.payload.get(VISUAL_SAMPLE_ENTRY_SIZE - 8..).unwrap_or_default();
+        
+        // Encrypted (encv) entries keep the original configuration box
+        let (codec, nal_length_size) = if let Some(avcc) = find_box(children, b"avcC") {
+            (VideoCodec::H264, (avcc.get(4).ok_or_else(|| anyhow!("Truncated avcC box"))? & 0x03) as usize + 1)
+        } else if let Some(hvcc) = find_box(children, b"hvcC") {
+            (VideoCodec::Hevc, (hvcc.get(21).ok_or_else(|| anyhow!("Truncated hvcC box"))? & 0x03) as usize + 1)
+        } else {
+            debug!("Unsupported video sample entry: {}", String::from_utf8_lossy(&entry.box_type));
+            continue;
+        };
+        
+        return Ok(Some(Fmp4Track {
+            track_id,
+            timescale,
+            codec,
+            nal_length_size,
+            default_sample_duration: 0,
+            default_sample_size: 0,
+        }));
+    }
+    
+    Ok(None)
+}
+
+// Walks the moof/mdat pairs of a media segment and returns the video samples
+// with their presentation times
+pub fn parse_media_segment<'a>(track: &Fmp4Track, data: &'a [u8]) -> Result<Vec<Fmp4Sample<'a>>> {
+    let boxes = parse_boxes(data);
+    let mut samples = Vec::new();
+    
+    for (index, moof) in boxes.iter().enumerate() {
+        if &moof.box_type != b"moof" {
+            continue;
+        }
+        
+        // Sample data without an explicit offset starts in the following mdat
+        let mdat_start = boxes[index + 1..]
+            .iter()
+            .find(|b| &b.box_type == b"mdat")
+            .map(|mdat| mdat.payload_offset);
+        
+        for traf in parse_boxes(moof.payload).into_iter().filter(|b| &b.box_type == b"traf") {
+            parse_track_fragment(track, data, moof.offset, mdat_start, traf.payload, &mut samples)?;
+        }
+    }
+    
+    Ok(samples)
+}
+
+fn parse_track_fragment<'a>(
+    track: &Fmp4Track,
+    data: &'a [u8],
+    moof_offset: usize,
+    mdat_start: Option<usize>,
+    traf: &[u8],
+    samples: &mut Vec<Fmp4Sample<'a>>,
+) -> Result<()> {
+    let tfhd = find_box(traf, b"tfhd").ok_or_else(|| anyhow!("Track fragment has no tfhd box"))?;
+    let tfhd_flags = read_u32(tfhd, 0)? & 0x00FF_FFFF;
+    if read_u32(tfhd, 4)? != track.track_id {
+        return Ok(());
+    }
+    
+    let mut pos = 8;
+    let mut base_data_offset = moof_offset as u64;
+    if tfhd_flags & TFHD_BASE_DATA_OFFSET != 0 {
+        base_data_offset = read_u64(tfhd, pos)?;
+        pos += 8;
+    }
+    if tfhd_flags & TFHD_SAMPLE_DESCRIPTION_INDEX != 0 {
+        pos += 4;
+    }
+    let mut default_duration = track.default_sample_duration;
+    if tfhd_flags & TFHD_DEFAULT_SAMPLE_DURATION != 0 {
+        default_duration = read_u32(tfhd, pos)?;
+        pos += 4;
+    }
+    let mut default_size = track.default_sample_size;
+    if tfhd_flags & TFHD_DEFAULT_SAMPLE_SIZE != 0 {
+        default_size = read_u32(tfhd, pos)?;
+    }
+    // default_sample_flags (TFHD_DEFAULT_SAMPLE_FLAGS) is not needed
+    
+    let mut decode_time = match find_box(traf, b"tfdt") {
+        Some(tfdt) if tfdt.first() == Some(&1) => read_u64(tfdt, 4)?,
+        Some(tfdt) => read_u32(tfdt, 4)? as u64,
+        None => 0,
+    };
+    
+    let timescale = track.timescale.max(1) as i128;
+    let mut next_offset = mdat_start.map(|start| start as u64);
+    
+    for trun in parse_boxes(traf).into_iter().filter(|b| &b.box_type == b"trun") {
+        let trun = trun.payload;
+        let flags = read_u32(trun, 0)? & 0x00FF_FFFF;
+        let version = trun[0];
+        let sample_count = read_u32(trun, 4)?;
+        let mut pos = 8;
+        
+        let mut offset = if flags & TRUN_DATA_OFFSET != 0 {
+            let data_offset = read_u32(trun, pos)? as i32;
+            pos += 4;
+            base_data_offset.checked_add_signed(data_offset as i64)
+        } else {
+            next_offset
+        }
+        .ok_or_else(|| anyhow!("Track run has no data offset"))?;
+        
+        if flags & TRUN_FIRST_SAMPLE_FLAGS != 0 {
+            pos += 4;
+        }
+        
+        for _ in 0..sample_count {
+            let mut duration = default_duration;
+            if flags & TRUN_SAMPLE_DURATION != 0 {
+                duration = read_u32(trun, pos)?;
+                pos += 4;
+            }
+            let mut size = default_size;
+            if flags & TRUN_SAMPLE_SIZE != 0 {
+                size = read_u32(trun, pos)?;
+                pos += 4;
+            }
+            if flags & TRUN_SAMPLE_FLAGS != 0 {
+                pos += 4;
+            }
+            let mut composition_offset = 0i64;
+            if flags & TRUN_SAMPLE_COMPOSITION_OFFSET != 0 {
+                let value = read_u32(trun, pos)?;
+                // Signed from version 1 on
+                composition_offset = if version == 0 { value as i64 } else { value as i32 as i64 };
+                pos += 4;
+            }
+            
+            let start = offset as usize;
+            let end = start + size as usize;
+            if end > data.len() {
+                return Err(anyhow!("Sample data at {}..{} is outside the segment", start, end));
+            }
+            
+            samples.push(Fmp4Sample {
+                pts: ((decode_time as i128 + composition_offset as i128) * 90000 / timescale).max(0) as u64,
+                data: &data[start..end],
+            });
+            
+            decode_time += duration as u64;
+            offset += size as u64;
+        }
+        
+        next_offset = Some(offset);
+    }
+    
+    Ok(())
+}
+
+// Splits a sample into its length-prefixed NAL units
+pub fn nal_units(sample: &[u8], nal_length_size: usize) -> Vec<&[u8]> {
+    let mut units = Vec::new();
+    let mut i = 0;
+    
+    while i + nal_length_size <= sample.len() {
+        let length = sample[i..i + nal_length_size]
+            .iter()
+            .fold(0usize, |length, &byte| (length << 8) | byte as usize);
+        i += nal_length_size;
+        
+        if i + length > sample.len() {
+            debug!("NAL unit length {} exceeds sample size", length);
+            break;
+        }
+        units.push(&sample[i..i + length]);
+        i += length;
+    }
+    
+    units
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACK: Fmp4Track = Fmp4Track {
+        track_id: 1,
+        timescale: 30000,
+        codec: VideoCodec::H264,
+        nal_length_size: 4,
+        default_sample_duration: 1001,
+        default_sample_size: 0,
+    };
+
+    fn mp4_box(box_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(box_type);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn full_box(box_type: &[u8; 4], version: u8, flags: u32, fields: &[u32]) -> Vec<u8> {
+        let mut payload = (flags | (version as u32) << 24).to_be_bytes().to_vec();
+        for field in fields {
+            payload.extend_from_slice(&field.to_be_bytes());
+        }
+        mp4_box(box_type, &payload)
+    }
+
+    // A moof with one track fragment, decoding from 3 s, followed by its mdat
+    fn media_segment(truns: &[Vec<u8>], mdat: &[u8]) -> Vec<u8> {
+        let mut traf = full_box(b"tfhd", 0, 0, &[TRACK.track_id]);
+        traf.extend(full_box(b"tfdt", 1, 0, &[0, 90000]));
+        for trun in truns {
+            traf.extend_from_slice(trun);
+        }
+        let mut moof = full_box(b"mfhd", 0, 0, &[1]);
+        moof.extend(mp4_box(b"traf", &traf));
+        
+        let mut segment = mp4_box(b"moof", &moof);
+        segment.extend(mp4_box(b"mdat", mdat));
+        segment
+    }
+
+    #[test]
+    fn applies_data_offset_and_composition_offsets() {
+        let flags = TRUN_DATA_OFFSET | TRUN_SAMPLE_DURATION | TRUN_SAMPLE_SIZE | TRUN_SAMPLE_COMPOSITION_OFFSET;
+        // The data offset is relative to the moof and skips a byte of the
+        // mdat; the second sample is presented before the first
+        let trun = |data_offset: u32| full_box(b"trun", 1, flags, &[2, data_offset, 1001, 4, 2002, 1001, 3, -1001i32 as u32]);
+        let mdat_payload_offset = media_segment(&[trun(0)], &[]).len() as u32;
+        let segment = media_segment(&[trun(mdat_payload_offset + 1)], b"xAAAABBB");
+        
+        let samples = parse_media_segment(&TRACK, &segment).unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].data, b"AAAA");
+        assert_eq!(samples[0].pts, (90000 + 2002) * 3);
+        assert_eq!(samples[1].data, b"BBB");
+        assert_eq!(samples[1].pts, (90000 + 1001 - 1001) * 3);
+    }
+
+    #[test]
+    fn runs_without_data_offset_follow_each_other_in_mdat() {
+        let trun = |size: u32| full_box(b"trun", 0, TRUN_SAMPLE_SIZE, &[1, size]);
+        let segment = media_segment(&[trun(2), trun(3)], b"AABBB");
+        
+        let samples = parse_media_segment(&TRACK, &segment).unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].data, b"AA");
+        assert_eq!(samples[0].pts, 270000);
+        // Timed with the default sample duration from trex
+        assert_eq!(samples[1].data, b"BBB");
+        assert_eq!(samples[1].pts, (90000 + 1001) * 3);
+    }
+
+    #[test]
+    fn rejects_samples_outside_the_segment() {
+        let trun = full_box(b"trun", 0, TRUN_SAMPLE_SIZE, &[1, 100]);
+        assert!(parse_media_segment(&TRACK, &media_segment(&[trun], b"AA")).is_err());
+    }
+
+    #[test]
+    fn splits_length_prefixed_nal_units() {
+        let sample = [0, 0, 0, 2, 0x06, 0x04, 0, 0, 0, 1, 0x65, 0, 0, 0, 9];
+        assert_eq!(nal_units(&sample, 4), vec![&[0x06, 0x04][..], &[0x65][..]]);
+    }
+}
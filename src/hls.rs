@@ -1,7 +1,14 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
 use anyhow::{Result, anyhow};
-use reqwest::Client;
+use bytes::{Bytes, BytesMut};
+use reqwest::{Client, Response};
+use tokio::io::{AsyncRead, AsyncReadExt};
 use url::Url;
-use log::{debug, info};
+use log::{debug, info, warn};
+use crate::encryption;
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
 
 #[derive(Debug, Clone)]
 pub enum PlaylistType {
@@ -18,21 +25,142 @@ pub struct MediaPlaylist {
 #[derive(Debug, Clone)]
 pub struct Segment {
     pub uri: String,
-    #[allow(dead_code)]
     pub duration: f64,
+    // Start of the segment on the playlist timeline (sum of earlier EXTINF durations)
+    pub start_time: f64,
+    pub sequence_number: u64,
+    // Set when the segment follows an EXT-X-DISCONTINUITY tag
+    pub discontinuity: bool,
+    // Init segment from EXT-X-MAP (fragmented MP4 / CMAF)
+    pub init_segment: Option<InitSegment>,
+    // Set when the segment is encrypted with METHOD=AES-128
+    pub key: Option<SegmentKey>,
+    // Cleared for standalone files, which have no EXTINF durations and are
+    // timed from their PTS alone
+    pub playlist_timing: bool,
+}
+
+impl Segment {
+    // A segment file given directly instead of through a playlist
+    pub fn standalone(uri: String, sequence_number: u64) -> Self {
+        Self {
+            uri,
+            duration: 0.0,
+            start_time: 0.0,
+            sequence_number,
+            discontinuity: false,
+            init_segment: None,
+            key: None,
+            playlist_timing: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SegmentKey {
+    pub uri: String,
+    pub iv: [u8; 16],
+}
+
+// An EXT-X-MAP init segment. An AES-128 EXT-X-KEY before the EXT-X-MAP tag
+// encrypts the init segment as well.
+#[derive(Debug, Clone)]
+pub struct InitSegment {
+    pub uri: String,
+    pub key: Option<InitSegmentKey>,
+}
+
+// Init segments have no media sequence number for the IV to default to, so
+// an EXT-X-KEY without an IV leaves it unknown and fetching the init segment
+// fails
+#[derive(Debug, Clone)]
+pub struct InitSegmentKey {
+    pub uri: String,
+    pub iv: Option<[u8; 16]>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EncryptionMethod {
+    Aes128,
+    // Segments are parsed as they are, without fetching the key
+    SampleAes,
+}
+
+// An EXT-X-KEY tag as written; the IV is only known per segment
+#[derive(Debug, Clone)]
+struct KeyTag {
+    method: EncryptionMethod,
+    uri: String,
+    iv: Option<[u8; 16]>,
+}
+
+// The body of a download, read chunk by chunk. Dropping it before the end
+// abandons the rest of the transfer.
+pub enum SegmentBody {
+    Http(Response),
+    Reader(Box<dyn AsyncRead + Unpin + Send>),
+}
+
+impl SegmentBody {
+    // The next chunk of data, or None at the end
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>> {
+        match self {
+            SegmentBody::Http(response) => Ok(response.chunk().await?),
+            SegmentBody::Reader(reader) => {
+                let mut chunk = BytesMut::with_capacity(READ_CHUNK_SIZE);
+                if reader.read_buf(&mut chunk).await? == 0 {
+                    return Ok(None);
+                }
+                Ok(Some(chunk.freeze()))
+            }
+        }
+    }
 }
 
 pub struct HlsParser {
     client: Client,
+    init_segments: Mutex<HashMap<String, Bytes>>,
+    keys: Mutex<HashMap<String, [u8; 16]>>,
+    // Start times by media sequence number, so a live playlist's sliding
+    // window keeps the timeline of earlier reloads
+    segment_start_times: Mutex<HashMap<u64, f64>>,
 }
 
 impl HlsParser {
     pub fn new(client: Client) -> Self {
-        Self { client }
+        Self {
+            client,
+            init_segments: Mutex::new(HashMap::new()),
+            keys: Mutex::new(HashMap::new()),
+            segment_start_times: Mutex::new(HashMap::new()),
+        }
     }
     
-    pub fn client(&self) -> &Client {
-        &self.client
+    // Fetches a playlist, segment, key or init segment. Besides HTTP(S) URLs
+    // this reads file:// URLs from disk and "-" from stdin.
+    pub async fn fetch(&self, uri: &str) -> Result<Bytes> {
+        let mut body = self.open(uri).await?;
+        let mut data = BytesMut::new();
+        while let Some(chunk) = body.chunk().await? {
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data.freeze())
+    }
+    
+    // Like fetch, but hands out the data as it arrives
+    pub async fn open(&self, uri: &str) -> Result<SegmentBody> {
+        if uri == "-" {
+            return Ok(SegmentBody::Reader(Box::new(tokio::io::stdin())));
+        }
+        
+        if let Some(path) = Url::parse(uri).ok().filter(|url| url.scheme() == "file").and_then(|url| url.to_file_path().ok()) {
+            let file = tokio::fs::File::open(&path).await
+                .map_err(|e| anyhow!("Unable to read {}: {}", path.display(), e))?;
+            return Ok(SegmentBody::Reader(Box::new(file)));
+        }
+        
+        let response = self.client.get(uri).send().await?.error_for_status()?;
+        Ok(SegmentBody::Http(response))
     }
     
     pub async fn determine_playlist_type(&self, playlist_url: &str) -> Result<PlaylistType> {
@@ -75,11 +203,43 @@ impl HlsParser {
         self.parse_media_playlist(&content, playlist_url)
     }
     
+    // Init segments are shared by many media segments, so each is only
+    // fetched (and decrypted) once
+    pub async fn fetch_init_segment(&self, init_segment: &InitSegment) -> Result<Bytes> {
+        if let Some(data) = self.init_segments.lock().unwrap().get(&init_segment.uri) {
+            return Ok(data.clone());
+        }
+        
+        debug!("Fetching init segment: {}", init_segment.uri);
+        let mut data = self.fetch(&init_segment.uri).await?;
+        if let Some(key) = &init_segment.key {
+            let iv = key.iv
+                .ok_or_else(|| anyhow!("Init segment {} follows an AES-128 EXT-X-KEY without an IV", init_segment.uri))?;
+            let key_data = self.fetch_key(&key.uri).await?;
+            data = encryption::decrypt_aes128(&key_data, &iv, &data)?.into();
+        }
+        self.init_segments.lock().unwrap().insert(init_segment.uri.clone(), data.clone());
+        Ok(data)
+    }
+    
+    // Keys are usually shared by many segments, so each is only fetched once
+    pub async fn fetch_key(&self, url: &str) -> Result<[u8; 16]> {
+        if let Some(key) = self.keys.lock().unwrap().get(url) {
+            return Ok(*key);
+        }
+        
+        debug!("Fetching key: {}", url);
+        let data = self.fetch(url).await?;
+        let key: [u8; 16] = data.as_ref().try_into()
+            .map_err(|_| anyhow!("AES-128 key {} is {} bytes, expected 16", url, data.len()))?;
+        self.keys.lock().unwrap().insert(url.to_string(), key);
+        Ok(key)
+    }
+    
     async fn fetch_playlist_content(&self, url: &str) -> Result<String> {
         debug!("Fetching playlist: {}", url);
-        let response = self.client.get(url).send().await?;
-        let content = response.text().await?;
-        Ok(content)
+        let data = self.fetch(url).await?;
+        Ok(String::from_utf8_lossy(&data).into_owned())
     }
     
     fn is_master_playlist(&self, content: &str) -> bool {
@@ -147,22 +307,72 @@ impl HlsParser {
     fn parse_media_playlist(&self, content: &str, base_url: &str) -> Result<Vec<Segment>> {
         let mut segments = Vec::new();
         let mut current_duration = 0.0;
+        let mut start_time = 0.0;
+        let mut discontinuity = false;
+        let mut init_segment = None;
+        let mut sequence_number = 0;
+        let mut key_tag: Option<KeyTag> = None;
         
         for line in content.lines() {
             let line = line.trim();
             
             if line.starts_with("#EXTINF:") {
                 current_duration = self.extract_duration(line);
+            } else if line == "#EXT-X-DISCONTINUITY" {
+                discontinuity = true;
+            } else if let Some(sequence) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+                sequence_number = sequence.trim().parse().unwrap_or(0);
+            } else if let Some(attributes) = line.strip_prefix("#EXT-X-KEY:") {
+                // Applies to every following segment until the next EXT-X-KEY.
+                // Only the default "identity" format is a plain AES-128 key;
+                // other formats belong to DRM systems and can be listed next
+                // to an identity key, so they are left aside.
+                match self.extract_attribute(attributes, "KEYFORMAT") {
+                    Some(key_format) if key_format != "identity" => debug!("Ignoring EXT-X-KEY with KEYFORMAT {}", key_format),
+                    _ => key_tag = self.parse_key_tag(attributes, base_url)?,
+                }
+            } else if let Some(attributes) = line.strip_prefix("#EXT-X-MAP:") {
+                // Applies to every following segment until the next EXT-X-MAP
+                init_segment = match self.extract_attribute(attributes, "URI") {
+                    Some(uri) => Some(InitSegment {
+                        uri: self.resolve_url(base_url, &uri)?,
+                        key: key_tag.as_ref()
+                            .filter(|tag| tag.method == EncryptionMethod::Aes128)
+                            .map(|tag| InitSegmentKey {
+                                uri: tag.uri.clone(),
+                                iv: tag.iv,
+                            }),
+                    }),
+                    None => None,
+                };
             } else if !line.starts_with('#') && !line.is_empty() {
                 let uri = self.resolve_url(base_url, line)?;
+                // Without an IV attribute the media sequence number is the IV
+                let key = key_tag.as_ref()
+                    .filter(|tag| tag.method == EncryptionMethod::Aes128)
+                    .map(|tag| SegmentKey {
+                        uri: tag.uri.clone(),
+                        iv: tag.iv.unwrap_or_else(|| (sequence_number as u128).to_be_bytes()),
+                    });
                 segments.push(Segment {
                     uri,
                     duration: current_duration,
+                    start_time,
+                    sequence_number,
+                    discontinuity,
+                    init_segment: init_segment.clone(),
+                    key,
+                    playlist_timing: true,
                 });
+                start_time += current_duration;
                 current_duration = 0.0;
+                discontinuity = false;
+                sequence_number += 1;
             }
         }
         
+        self.align_start_times(&mut segments);
+        
         info!("Found {} segments in media playlist", segments.len());
         Ok(segments)
     }
@@ -176,6 +386,83 @@ impl HlsParser {
         0.0
     }
     
+    // Shifts the segments so that one seen in an earlier load of the playlist
+    // keeps its start time. Segments that slid out of the playlist are
+    // forgotten, so a live playlist does not grow the map forever.
+    fn align_start_times(&self, segments: &mut [Segment]) {
+        let mut start_times = self.segment_start_times.lock().unwrap();
+        
+        let shift = segments.iter()
+            .find_map(|segment| start_times.get(&segment.sequence_number).map(|start| start - segment.start_time))
+            .unwrap_or(0.0);
+        
+        if let Some(first) = segments.first() {
+            start_times.retain(|&sequence_number, _| sequence_number >= first.sequence_number);
+        }
+        for segment in segments.iter_mut() {
+            segment.start_time += shift;
+            start_times.insert(segment.sequence_number, segment.start_time);
+        }
+    }
+    
+    fn parse_key_tag(&self, attributes: &str, base_url: &str) -> Result<Option<KeyTag>> {
+        let method = self.extract_attribute(attributes, "METHOD").unwrap_or_default();
+        let method = match method.as_str() {
+            "NONE" => return Ok(None),
+            "AES-128" => EncryptionMethod::Aes128,
+            "SAMPLE-AES" => EncryptionMethod::SampleAes,
+            _ => {
+                warn!("Unsupported encryption method {}, segments will be parsed as is", method);
+                return Ok(None);
+            }
+        };
+        
+        let uri = self.extract_attribute(attributes, "URI")
+            .ok_or_else(|| anyhow!("EXT-X-KEY with METHOD={:?} has no URI", method))?;
+        let iv = match self.extract_attribute(attributes, "IV") {
+            Some(iv) => Some(self.parse_iv(&iv)?),
+            None => None,
+        };
+        
+        Ok(Some(KeyTag {
+            method,
+            uri: self.resolve_url(base_url, &uri)?,
+            iv,
+        }))
+    }
+    
+    // IVs are written as a 128-bit hexadecimal integer with a 0x prefix
+    fn parse_iv(&self, iv: &str) -> Result<[u8; 16]> {
+        let hex = iv.strip_prefix("0x").or_else(|| iv.strip_prefix("0X")).unwrap_or(iv);
+        let value = u128::from_str_radix(hex, 16)
+            .map_err(|e| anyhow!("Invalid EXT-X-KEY IV {}: {}", iv, e))?;
+        Ok(value.to_be_bytes())
+    }
+    
+    // Reads an attribute from a tag's attribute list, removing quotes
+    fn extract_attribute(&self, attributes: &str, name: &str) -> Option<String> {
+        let mut rest = attributes;
+        
+        while !rest.is_empty() {
+            let (key, value) = rest.split_once('=')?;
+            let (value, remainder) = if let Some(quoted) = value.strip_prefix('"') {
+                let end = quoted.find('"')?;
+                (&quoted[..end], quoted[end + 1..].trim_start_matches(','))
+            } else {
+                match value.split_once(',') {
+                    Some((value, remainder)) => (value, remainder),
+                    None => (value, ""),
+                }
+            };
+            
+            if key.trim() == name {
+                return Some(value.to_string());
+            }
+            rest = remainder;
+        }
+        None
+    }
+    
     fn resolve_url(&self, base_url: &str, relative_url: &str) -> Result<String> {
         if relative_url.starts_with("http://") || relative_url.starts_with("https://") {
             return Ok(relative_url.to_string());
@@ -185,4 +472,167 @@ impl HlsParser {
         let resolved = base.join(relative_url)?;
         Ok(resolved.to_string())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 16] = [
+        0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f, 0x3c,
+    ];
+    // "caption" encrypted with KEY and IV 7
+    const ENCRYPTED_INIT: [u8; 16] = [
+        0x4a, 0xa7, 0x60, 0x0b, 0xb2, 0x1c, 0x8c, 0x7a, 0x19, 0x63, 0xb0, 0x93, 0x8d, 0xb7, 0x98, 0x0d,
+    ];
+
+    fn parser() -> HlsParser {
+        HlsParser::new(Client::new())
+    }
+
+    // A directory of its own for each test, as a file:// base URL
+    fn test_dir(name: &str) -> (std::path::PathBuf, String) {
+        let dir = std::env::temp_dir().join(format!("hlscaptionfinder-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        let base_url = Url::from_directory_path(&dir).unwrap().to_string();
+        (dir, base_url)
+    }
+
+    #[test]
+    fn iv_defaults_to_media_sequence_number() {
+        let playlist = "#EXTM3U\n\
+            #EXT-X-MEDIA-SEQUENCE:41\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\"\n\
+            #EXTINF:6.0,\n\
+            a.ts\n\
+            #EXTINF:6.0,\n\
+            b.ts\n";
+        let segments = parser().parse_media_playlist(playlist, "http://example.com/live/index.m3u8").unwrap();
+        
+        let keys: Vec<_> = segments.iter().map(|segment| segment.key.clone().unwrap()).collect();
+        assert_eq!(keys[0].uri, "http://example.com/live/key.bin");
+        assert_eq!(keys[0].iv, 41u128.to_be_bytes());
+        assert_eq!(keys[1].iv, 42u128.to_be_bytes());
+    }
+
+    #[test]
+    fn parses_explicit_iv() {
+        let playlist = "#EXTM3U\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\",IV=0x000102030405060708090A0B0C0D0E0F\n\
+            #EXTINF:6.0,\n\
+            a.ts\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\",IV=0X1f\n\
+            #EXTINF:6.0,\n\
+            b.ts\n\
+            #EXT-X-KEY:METHOD=NONE\n\
+            #EXTINF:6.0,\n\
+            c.ts\n";
+        let segments = parser().parse_media_playlist(playlist, "http://example.com/index.m3u8").unwrap();
+        
+        let expected: [u8; 16] = core::array::from_fn(|i| i as u8);
+        assert_eq!(segments[0].key.as_ref().unwrap().iv, expected);
+        assert_eq!(segments[1].key.as_ref().unwrap().iv, 0x1fu128.to_be_bytes());
+        assert!(segments[2].key.is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_iv() {
+        let playlist = "#EXTM3U\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\",IV=0xnothex\n\
+            #EXTINF:6.0,\n\
+            a.ts\n";
+        assert!(parser().parse_media_playlist(playlist, "http://example.com/index.m3u8").is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_key_length() {
+        let (dir, base_url) = test_dir("bad-key");
+        std::fs::write(dir.join("key.bin"), &KEY[..15]).unwrap();
+        
+        let error = parser().fetch_key(&format!("{}key.bin", base_url)).await.unwrap_err();
+        assert!(error.to_string().contains("is 15 bytes, expected 16"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn decrypts_init_segment_with_active_key() {
+        let (dir, base_url) = test_dir("init-segment");
+        std::fs::write(dir.join("key.bin"), KEY).unwrap();
+        std::fs::write(dir.join("init.mp4"), ENCRYPTED_INIT).unwrap();
+        std::fs::write(dir.join("clear.mp4"), b"clear").unwrap();
+        
+        // The first EXT-X-MAP comes before the key and stays clear
+        let playlist = "#EXTM3U\n\
+            #EXT-X-MAP:URI=\"clear.mp4\"\n\
+            #EXTINF:6.0,\n\
+            a.m4s\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\",IV=0x7\n\
+            #EXT-X-MAP:URI=\"init.mp4\"\n\
+            #EXTINF:6.0,\n\
+            b.m4s\n";
+        let parser = parser();
+        let segments = parser.parse_media_playlist(playlist, &format!("{}index.m3u8", base_url)).unwrap();
+        
+        let clear = segments[0].init_segment.as_ref().unwrap();
+        assert!(clear.key.is_none());
+        assert_eq!(parser.fetch_init_segment(clear).await.unwrap().as_ref(), b"clear");
+        let encrypted = segments[1].init_segment.as_ref().unwrap();
+        assert_eq!(encrypted.key.as_ref().unwrap().iv, Some(7u128.to_be_bytes()));
+        assert_eq!(parser.fetch_init_segment(encrypted).await.unwrap().as_ref(), b"caption");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn live_reloads_keep_the_timeline_and_forget_old_segments() {
+        let parser = parser();
+        let base_url = "http://example.com/live/index.m3u8";
+        parser.parse_media_playlist("#EXT-X-MEDIA-SEQUENCE:10\n#EXTINF:4.0,\na.ts\n#EXTINF:4.0,\nb.ts\n", base_url).unwrap();
+        
+        let segments = parser
+            .parse_media_playlist("#EXT-X-MEDIA-SEQUENCE:11\n#EXTINF:4.0,\nb.ts\n#EXTINF:4.0,\nc.ts\n", base_url)
+            .unwrap();
+        let start_times: Vec<f64> = segments.iter().map(|segment| segment.start_time).collect();
+        assert_eq!(start_times, vec![4.0, 8.0]);
+        
+        let mut remembered: Vec<u64> = parser.segment_start_times.lock().unwrap().keys().copied().collect();
+        remembered.sort();
+        assert_eq!(remembered, vec![11, 12]);
+    }
+
+    #[tokio::test]
+    async fn encrypted_init_segment_needs_iv_when_fetched() {
+        let (dir, base_url) = test_dir("init-segment-iv");
+        std::fs::write(dir.join("key.bin"), KEY).unwrap();
+        std::fs::write(dir.join("init.mp4"), ENCRYPTED_INIT).unwrap();
+        
+        // The playlist itself is usable; only the init segment is not
+        let playlist = "#EXTM3U\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\"\n\
+            #EXT-X-MAP:URI=\"init.mp4\"\n\
+            #EXTINF:6.0,\n\
+            a.m4s\n";
+        let parser = parser();
+        let segments = parser.parse_media_playlist(playlist, &format!("{}index.m3u8", base_url)).unwrap();
+        
+        let init_segment = segments[0].init_segment.as_ref().unwrap();
+        let error = parser.fetch_init_segment(init_segment).await.unwrap_err();
+        assert!(error.to_string().contains("without an IV"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn only_identity_keys_are_applied() {
+        let playlist = "#EXTM3U\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\",KEYFORMAT=\"identity\"\n\
+            #EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"skd://key\",KEYFORMAT=\"com.apple.streamingkeydelivery\"\n\
+            #EXTINF:6.0,\n\
+            a.ts\n\
+            #EXT-X-KEY:METHOD=NONE\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"drm.bin\",KEYFORMAT=\"com.example.drm\"\n\
+            #EXTINF:6.0,\n\
+            b.ts\n";
+        let segments = parser().parse_media_playlist(playlist, "http://example.com/index.m3u8").unwrap();
+        
+        assert_eq!(segments[0].key.as_ref().unwrap().uri, "http://example.com/key.bin");
+        assert!(segments[1].key.is_none());
+    }
+}
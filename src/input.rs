@@ -0,0 +1,179 @@
+use std::path::Path;
+use anyhow::{Result, anyhow};
+use url::Url;
+use crate::hls::Segment;
+use crate::udp_input::UdpSource;
+
+const PLAYLIST_EXTENSIONS: [&str; 2] = ["m3u8", "m3u"];
+const SEGMENT_EXTENSIONS: [&str; 1] = ["ts"];
+
+// What the command line points at: an HLS playlist (remote or on disk),
+// transport stream segments to decode directly, or a transport stream
+// received over UDP
+pub enum Input {
+    Playlist(String),
+    Segments(Vec<Segment>),
+    Udp(UdpSource),
+}
+
+impl Input {
+    pub fn parse(input: &str) -> Result<Self> {
+        if input == "-" {
+            return Ok(Input::Segments(vec![Segment::standalone(input.to_string(), 0)]));
+        }
+        
+        if let Some(source) = UdpSource::parse(input)? {
+            return Ok(Input::Udp(source));
+        }
+        
+        if let Ok(url) = Url::parse(input) {
+            if matches!(url.scheme(), "http" | "https" | "file") {
+                return Ok(Input::Playlist(input.to_string()));
+            }
+        }
+        
+        let path = Path::new(input);
+        if path.is_dir() {
+            return Self::directory(path);
+        }
+        if !path.is_file() {
+            return Err(anyhow!("{} is neither a URL nor an existing file or directory", input));
+        }
+        
+        // Local playlists become file:// URLs so their relative segment,
+        // key and init segment URIs resolve to files next to them
+        let url = file_url(path)?;
+        if has_extension(path, &PLAYLIST_EXTENSIONS) {
+            Ok(Input::Playlist(url))
+        } else {
+            Ok(Input::Segments(vec![Segment::standalone(url, 0)]))
+        }
+    }
+
+    // The .ts files of a directory, in name order
+    fn directory(path: &Path) -> Result<Self> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            let file = entry?.path();
+            if file.is_file() && has_extension(&file, &SEGMENT_EXTENSIONS) {
+                files.push(file);
+            }
+        }
+        files.sort();
+        
+        if files.is_empty() {
+            return Err(anyhow!("No .ts segments found in {}", path.display()));
+        }
+        
+        let segments = files
+            .iter()
+            .enumerate()
+            .map(|(index, file)| Ok(Segment::standalone(file_url(file)?, index as u64)))
+            .collect::<Result<_>>()?;
+        Ok(Input::Segments(segments))
+    }
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extensions.iter().any(|candidate| extension.eq_ignore_ascii_case(candidate)))
+}
+
+fn file_url(path: &Path) -> Result<String> {
+    let path = path.canonicalize()?;
+    Url::from_file_path(&path)
+        .map(|url| url.to_string())
+        .map_err(|_| anyhow!("Unable to turn {} into a file URL", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // A directory of its own for each test, holding empty files
+    fn test_dir(name: &str, files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hlscaptionfinder-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in files {
+            std::fs::write(dir.join(file), b"").unwrap();
+        }
+        dir.canonicalize().unwrap()
+    }
+
+    fn url(path: &Path) -> String {
+        Url::from_file_path(path).unwrap().to_string()
+    }
+
+    fn segments(input: Input) -> Vec<(String, u64)> {
+        match input {
+            Input::Segments(segments) => segments.into_iter().map(|segment| (segment.uri, segment.sequence_number)).collect(),
+            _ => panic!("expected segments"),
+        }
+    }
+
+    #[test]
+    fn single_file_is_one_segment() {
+        let dir = test_dir("input-file", &["only.ts"]);
+        let file = dir.join("only.ts");
+        
+        let input = Input::parse(file.to_str().unwrap()).unwrap();
+        
+        assert_eq!(segments(input), [(url(&file), 0)]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn directory_lists_ts_files_by_name() {
+        let dir = test_dir("input-dir", &["2.ts", "10.ts", "1.TS", "notes.txt", "index.m3u8"]);
+        std::fs::create_dir_all(dir.join("nested.ts")).unwrap();
+        
+        let input = Input::parse(dir.to_str().unwrap()).unwrap();
+        
+        assert_eq!(segments(input), [
+            (url(&dir.join("1.TS")), 0),
+            (url(&dir.join("10.ts")), 1),
+            (url(&dir.join("2.ts")), 2),
+        ]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn directory_without_segments_is_an_error() {
+        let dir = test_dir("input-empty", &["notes.txt"]);
+        
+        assert!(Input::parse(dir.to_str().unwrap()).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn local_playlist_becomes_file_url() {
+        let dir = test_dir("input-playlist", &["index.m3u8"]);
+        let playlist = dir.join("index.m3u8");
+        
+        match Input::parse(playlist.to_str().unwrap()).unwrap() {
+            Input::Playlist(playlist_url) => {
+                assert!(playlist_url.starts_with("file://"));
+                assert_eq!(playlist_url, url(&playlist));
+            }
+            _ => panic!("expected a playlist"),
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dash_reads_stdin() {
+        assert_eq!(segments(Input::parse("-").unwrap()), [("-".to_string(), 0)]);
+    }
+
+    #[test]
+    fn urls_and_streams_are_kept() {
+        assert!(matches!(
+            Input::parse("https://example.com/index.m3u8").unwrap(),
+            Input::Playlist(url) if url == "https://example.com/index.m3u8"
+        ));
+        assert!(matches!(Input::parse("udp://@239.0.0.1:1234").unwrap(), Input::Udp(_)));
+        assert!(Input::parse("/nonexistent/hlscaptionfinder/input.ts").is_err());
+    }
+}
@@ -0,0 +1,44 @@
+// Finds and decodes CEA-608/708 captions in HLS streams and MPEG-TS.
+//
+// CaptionReader is the entry point: it opens a playlist URL, local files, a
+// UDP stream or any byte source, and hands out captions with their timing,
+// channel and segment. The stages it is built from can be used on their own:
+//
+//   CaptionExtractor  transport stream bytes or fMP4 segments → captions
+//   TsDemuxer         transport stream packets → video stream payloads
+//   Cea708Parser      SEI or MPEG-2 user data → cc_data
+//   CaptionDecoder    cc_data in presentation order → captions
+//
+// The writers turn captions (or their cc_data) into subtitle and caption
+// files.
+
+pub mod caption_decoder;
+pub mod caption_event;
+pub mod caption_extractor;
+pub mod caption_reader;
+pub mod cc_reorder;
+pub mod cea708;
+pub mod fmp4;
+pub mod hls;
+pub mod input;
+pub mod mcc_writer;
+pub mod nal;
+pub mod pes;
+pub mod scc_writer;
+pub mod subtitle_writer;
+pub mod timecode;
+pub mod ts_demux;
+pub mod ttml_writer;
+pub mod udp_input;
+mod cea608;
+mod cea708_decoder;
+mod encryption;
+
+pub use caption_decoder::CaptionDecoder;
+pub use caption_event::{CaptionChannel, CaptionEvent, CaptionTime};
+pub use caption_extractor::CaptionExtractor;
+pub use caption_reader::{Caption, CaptionReader, DecodedCaptions, ReaderEvent, ReaderOptions, SegmentCaptions, SourceKind};
+pub use cea708::{CaptionData, Cea708Parser};
+pub use hls::Segment;
+pub use input::Input;
+pub use ts_demux::TsDemuxer;
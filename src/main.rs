@@ -1,28 +1,46 @@
-use std::time::Duration;
-use anyhow::Result;
+use std::path::PathBuf;
+use anyhow::{Result, anyhow};
 use clap::Parser;
 use log::{info, warn, error};
 use reqwest::Client;
-use tokio::time::sleep;
+use hlscaptionfinder::{cc_reorder, CaptionChannel, CaptionEvent, CaptionReader, DecodedCaptions, Input, ReaderEvent, ReaderOptions, SegmentCaptions, SourceKind};
 
-mod hls;
-mod mpeg_ts;
-mod caption;
-mod mpeg_bitstream;
-mod cea708;
-mod cea608;
-mod libcaption_compat;
-mod optimized_ts_parser;
+mod caption_output;
+mod report;
 
-use hls::{HlsParser, PlaylistType};
-use optimized_ts_parser::OptimizedTsParser;
+use caption_output::{CaptionOutput, OutputFormat};
+use report::{ReportFormat, Reporter, SegmentReport};
 
 #[derive(Parser)]
 #[command(name = "hlscaptionfinder")]
-#[command(about = "A CLI tool to find captions in HLS streams")]
+#[command(about = "A CLI tool to find captions in HLS streams and MPEG-TS files")]
 struct Args {
-    #[arg(help = "HLS playlist URL")]
-    playlist_url: String,
+    #[arg(help = "HLS playlist URL, local playlist, .ts file, directory of .ts segments, udp://@group:port stream, or - to read a TS from stdin")]
+    input: String,
+    
+    #[arg(long = "channel", value_name = "CHANNEL", help = "Only report captions from this channel (CC1-CC4, T1-T4, SERVICE1-SERVICE63); may be repeated")]
+    channels: Vec<CaptionChannel>,
+    
+    #[arg(long, help = "List the caption channels and CEA-708 services found in each segment")]
+    list_channels: bool,
+    
+    #[arg(long, value_name = "SERVICE", value_parser = clap::value_parser!(u8).range(1..=63), help = "Print the raw bytes of a CEA-708 service (1-63) for each segment")]
+    dump_service: Option<u8>,
+    
+    #[arg(long, value_name = "FRAMES", default_value_t = cc_reorder::DEFAULT_REORDER_DEPTH, help = "Number of frames of caption data held back to restore presentation order with B-frames (0 disables reordering)")]
+    reorder_depth: usize,
+    
+    #[arg(long, value_name = "PATH", help = "Write the captions to a subtitle or caption file")]
+    output: Option<PathBuf>,
+    
+    #[arg(long, value_enum, requires = "output", help = "Output file format (defaults to the output file extension)")]
+    output_format: Option<OutputFormat>,
+    
+    #[arg(long, value_enum, default_value = "text", help = "Format of the report printed to stdout")]
+    format: ReportFormat,
+    
+    #[arg(long, conflicts_with = "output", help = "Stop reading each segment once it has carried caption data; only tells which segments carry captions")]
+    presence_only: bool,
 }
 
 #[tokio::main]
@@ -30,188 +48,229 @@ async fn main() -> Result<()> {
     env_logger::init();
     
     let args = Args::parse();
-    let client = Client::new();
     
-    info!("Starting HLS Caption Finder for: {}", args.playlist_url);
+    info!("Starting HLS Caption Finder for: {}", args.input);
     
-    let hls_parser = HlsParser::new(client.clone());
-    let playlist_type = hls_parser.determine_playlist_type(&args.playlist_url).await?;
+    let mut writer = match &args.output {
+        Some(path) => {
+            let format = args.output_format
+                .or_else(|| OutputFormat::from_path(path))
+                .ok_or_else(|| anyhow!("Unknown output format for {}, use --output-format", path.display()))?;
+            Some(CaptionOutput::create(path, format, &args.channels)?)
+        }
+        None => None,
+    };
+    let mut reporter = Reporter::new(args.format, &args.input, args.list_channels, args.dump_service);
     
-    match playlist_type {
-        PlaylistType::Live { target_duration } => {
-            info!("Detected live playlist with target duration: {}s", target_duration);
-            process_live_playlist(&hls_parser, &args.playlist_url, target_duration).await?;
+    let input = Input::parse(&args.input)?;
+    let udp_source = match &input {
+        Input::Udp(source) => Some(source.to_string()),
+        _ => None,
+    };
+    if udp_source.is_some() {
+        // A stream has no segments to list channels for or build a report from
+        if args.format == ReportFormat::Json {
+            return Err(anyhow!("--format json needs a VOD playlist, use --format ndjson for UDP streams"));
+        }
+        if args.list_channels || args.dump_service.is_some() {
+            return Err(anyhow!("--list-channels and --dump-service report per segment and are not available for UDP streams"));
+        }
+    }
+    
+    let options = ReaderOptions {
+        reorder_depth: args.reorder_depth,
+        presence_only: args.presence_only,
+        presence_channels: args.channels.clone(),
+        record_cc_data: writer.as_ref().is_some_and(|writer| writer.needs_cc_data()),
+    };
+    let mut reader = CaptionReader::open_input(Client::new(), input, options).await?;
+    
+    match reader.kind() {
+        SourceKind::Segments => {
+            process_segments(&args, &mut reader, &mut writer, &mut reporter).await?;
+        }
+        SourceKind::Live { .. } => {
+            // A live playlist never ends, so there is no complete report to print
+            if args.format == ReportFormat::Json {
+                return Err(anyhow!("--format json needs a VOD playlist, use --format ndjson for live playlists"));
+            }
+            if writer.as_ref().is_some_and(|writer| writer.needs_playlist_end()) {
+                return Err(anyhow!("TTML output needs a VOD playlist, use SRT or WebVTT for live playlists"));
+            }
+            process_live_playlist(&args, &mut reader, &mut writer, &mut reporter).await?;
         }
-        PlaylistType::Vod => {
-            info!("Detected VOD playlist");
-            process_vod_playlist(&hls_parser, &args.playlist_url).await?;
+        SourceKind::Stream => {
+            if args.format == ReportFormat::Text {
+                println!("Listening for MPEG-TS on {}, press Ctrl+C to stop", udp_source.unwrap_or_default());
+            }
+            process_stream(&args, &mut reader, &mut writer, &mut reporter).await?;
         }
     }
     
+    if let Some(writer) = writer.as_mut() {
+        writer.finish()?;
+    }
+    
     Ok(())
 }
 
-async fn process_live_playlist(
-    hls_parser: &HlsParser,
-    playlist_url: &str,
-    target_duration: u32,
+// Processes a complete list of segments, from a VOD playlist or local files
+async fn process_segments(
+    args: &Args,
+    reader: &mut CaptionReader,
+    writer: &mut Option<CaptionOutput>,
+    reporter: &mut Reporter,
 ) -> Result<()> {
-    let mut processed_segments = std::collections::HashSet::new();
-    let poll_interval = Duration::from_secs(target_duration as u64);
-    
-    info!("Starting live playlist polling every {}s", target_duration);
+    let mut total_segments = 0;
+    let mut processed_count = 0;
     
-    loop {
-        match process_current_segments(hls_parser, playlist_url, &mut processed_segments).await {
-            Ok(_) => {
-                info!("Completed live playlist poll cycle");
+    while let Some(event) = reader.next_event().await? {
+        match event {
+            ReaderEvent::SegmentsFound(count) => {
+                total_segments = count;
+                reporter.playlist_found(total_segments);
             }
-            Err(e) => {
-                error!("Error processing segments: {}", e);
+            ReaderEvent::SegmentStarted { number, uri } => {
+                reporter.segment_started(number, total_segments, &uri);
             }
+            ReaderEvent::Segment(segment_captions) => {
+                processed_count += 1;
+                report_segment(args, writer, reporter, *segment_captions)?;
+                
+                if processed_count % 10 == 0 {
+                    reporter.progress(processed_count, total_segments);
+                    info!("Progress: {}/{} segments processed ({:.1}%)", 
+                          processed_count, total_segments, 
+                          (processed_count as f64 / total_segments as f64) * 100.0);
+                }
+            }
+            // Captions still on screen when the playlist ends
+            ReaderEvent::Finished(decoded) => {
+                let captions = write_captions(args, writer, decoded)?;
+                reporter.final_captions(captions)?;
+            }
+            ReaderEvent::Captions(_) | ReaderEvent::Error(_) | ReaderEvent::PollFinished => {}
         }
-        
-        sleep(poll_interval).await;
     }
+    
+    reporter.finished(total_segments)
 }
 
-async fn process_vod_playlist(hls_parser: &HlsParser, playlist_url: &str) -> Result<()> {
-    let mut processed_segments = std::collections::HashSet::new();
-    
-    info!("Processing all segments in VOD playlist");
-    
-    let segments = hls_parser.get_lowest_bitrate_segments(playlist_url).await?;
-    let total_segments = segments.len();
-    println!("Found {} segments to process", total_segments);
-    info!("Found {} segments to process", total_segments);
-    
-    let mut segments_with_captions = 0;
-    let mut total_captions = 0;
-    
-    process_current_segments_with_progress(
-        hls_parser, 
-        playlist_url, 
-        &mut processed_segments,
-        &mut segments_with_captions,
-        &mut total_captions,
-        total_segments
-    ).await?;
-    
-    println!("Completed processing all segments");
-    println!("Summary: {}/{} segments contained captions ({} total captions found)", 
-          segments_with_captions, total_segments, total_captions);
-    info!("Completed processing all segments");
-    info!("Summary: {}/{} segments contained captions ({} total captions found)", 
-          segments_with_captions, total_segments, total_captions);
+async fn process_live_playlist(
+    args: &Args,
+    reader: &mut CaptionReader,
+    writer: &mut Option<CaptionOutput>,
+    reporter: &mut Reporter,
+) -> Result<()> {
+    while let Some(event) = reader.next_event().await? {
+        match event {
+            ReaderEvent::Segment(segment_captions) => {
+                report_segment(args, writer, reporter, *segment_captions)?;
+            }
+            ReaderEvent::Error(e) => {
+                error!("Error processing segments: {}", e);
+                reporter.error(&e.to_string())?;
+            }
+            ReaderEvent::PollFinished => reporter.poll_cycle()?,
+            _ => {}
+        }
+    }
     Ok(())
 }
 
-async fn process_current_segments(
-    hls_parser: &HlsParser,
-    playlist_url: &str,
-    processed_segments: &mut std::collections::HashSet<String>,
+// Decodes a transport stream received over UDP until interrupted, printing
+// captions as soon as they are complete
+async fn process_stream(
+    args: &Args,
+    reader: &mut CaptionReader,
+    writer: &mut Option<CaptionOutput>,
+    reporter: &mut Reporter,
 ) -> Result<()> {
-    let segments = hls_parser.get_lowest_bitrate_segments(playlist_url).await?;
-    let client = hls_parser.client();
-    let mut optimized_parser = OptimizedTsParser::new();
-    
-    for segment in segments {
-        if processed_segments.contains(&segment.uri) {
-            continue;
-        }
-        
-        info!("Processing segment: {}", segment.uri);
+    loop {
+        let event = tokio::select! {
+            event = reader.next_event() => event?,
+            _ = tokio::signal::ctrl_c() => break,
+        };
         
-        match download_and_process_segment(
-            client,
-            &segment.uri,
-            &mut optimized_parser,
-        ).await {
-            Ok(captions) => {
-                if !captions.is_empty() {
-                    println!("Segment: {}", segment.uri);
-                    for caption in captions {
-                        println!("  Caption: {}", caption);
-                    }
-                }
+        match event {
+            Some(ReaderEvent::Captions(decoded) | ReaderEvent::Finished(decoded)) => {
+                let captions = write_captions(args, writer, decoded)?;
+                reporter.stream_captions(&captions)?;
             }
-            Err(e) => {
-                warn!("Failed to process segment {}: {}", segment.uri, e);
+            Some(ReaderEvent::Error(e)) => {
+                warn!("Failed to process stream data: {}", e);
+                reporter.error(&e.to_string())?;
             }
+            Some(_) => {}
+            None => break,
         }
-        
-        processed_segments.insert(segment.uri);
     }
     
+    let captions = write_captions(args, writer, reader.finish()?)?;
+    reporter.stream_captions(&captions)?;
+    
+    reporter.stream_finished();
     Ok(())
 }
 
-async fn process_current_segments_with_progress(
-    hls_parser: &HlsParser,
-    playlist_url: &str,
-    processed_segments: &mut std::collections::HashSet<String>,
-    segments_with_captions: &mut usize,
-    total_captions: &mut usize,
-    total_segments: usize,
+// Writes the selected captions and the cc_data to the output file, and
+// returns the selected captions
+fn write_captions(args: &Args, writer: &mut Option<CaptionOutput>, decoded: DecodedCaptions) -> Result<Vec<CaptionEvent>> {
+    let captions: Vec<_> = decoded.captions.into_iter().filter(|caption| is_selected(args, caption)).collect();
+    if let Some(writer) = writer.as_mut() {
+        for caption in &captions {
+            writer.write_caption(caption)?;
+        }
+        writer.write_cc_data(decoded.cc_data)?;
+    }
+    Ok(captions)
+}
+
+// Reports the selected captions of a segment, or its error
+fn report_segment(
+    args: &Args,
+    writer: &mut Option<CaptionOutput>,
+    reporter: &mut Reporter,
+    segment_captions: SegmentCaptions,
 ) -> Result<()> {
-    let segments = hls_parser.get_lowest_bitrate_segments(playlist_url).await?;
-    let client = hls_parser.client();
-    let mut optimized_parser = OptimizedTsParser::new();
+    let segment = segment_captions.segment;
+    let carries_captions = segment_captions.channels.iter().any(|&channel| is_selected_channel(args, channel))
+        || segment_captions.services.keys().any(|&service| is_selected_channel(args, CaptionChannel::Service(service)));
+    let mut report = SegmentReport {
+        uri: segment.uri.clone(),
+        sequence_number: segment.sequence_number,
+        start_time: segment.start_time,
+        duration: segment.duration,
+        channels: segment_captions.channels,
+        services: segment_captions.services.keys().copied().collect(),
+        carries_captions,
+        service_data: None,
+        error: None,
+        captions: Vec::new(),
+    };
     
-    let mut processed_count = 0;
+    if let Some(e) = segment_captions.error {
+        warn!("Failed to process segment {}: {}", segment.uri, e);
+        report.error = Some(e.to_string());
+    }
     
-    for segment in segments {
-        if processed_segments.contains(&segment.uri) {
-            continue;
-        }
-        
-        processed_count += 1;
-        println!("Processing segment {}/{}: {}", processed_count, total_segments, segment.uri);
-        
-        match download_and_process_segment(
-            client,
-            &segment.uri,
-            &mut optimized_parser,
-        ).await {
-            Ok(captions) => {
-                if !captions.is_empty() {
-                    *segments_with_captions += 1;
-                    let caption_count = captions.len();
-                    *total_captions += caption_count;
-                    println!("Segment: {}", segment.uri);
-                    for caption in captions {
-                        println!("  Caption: {}", caption);
-                    }
-                }
-            }
-            Err(e) => {
-                warn!("Failed to process segment {}: {}", segment.uri, e);
-            }
-        }
-        
-        processed_segments.insert(segment.uri);
-        
-        if processed_count % 10 == 0 {
-            println!("Progress: {}/{} segments processed ({:.1}%)", 
-                  processed_count, total_segments, 
-                  (processed_count as f64 / total_segments as f64) * 100.0);
-            info!("Progress: {}/{} segments processed ({:.1}%)", 
-                  processed_count, total_segments, 
-                  (processed_count as f64 / total_segments as f64) * 100.0);
-        }
+    if let Some(service_data) = args.dump_service.and_then(|service| segment_captions.services.get(&service)).filter(|data| !data.is_empty()) {
+        let hex: Vec<_> = service_data.iter().map(|byte| format!("{:02x}", byte)).collect();
+        report.service_data = Some(hex.join(" "));
     }
     
-    Ok(())
+    report.captions = write_captions(args, writer, DecodedCaptions {
+        captions: segment_captions.captions,
+        cc_data: segment_captions.cc_data,
+    })?;
+    
+    reporter.segment_processed(report)
 }
 
-async fn download_and_process_segment(
-    client: &Client,
-    segment_url: &str,
-    optimized_parser: &mut OptimizedTsParser,
-) -> Result<Vec<String>> {
-    let response = client.get(segment_url).send().await?;
-    let segment_data = response.bytes().await?;
-    // Use optimized TS parser with PAT/PMT/video PID filtering and NALU type 6 checking
-    let captions = optimized_parser.parse_ts_file(&segment_data)?;
-    Ok(captions)
-}
\ No newline at end of file
+fn is_selected(args: &Args, caption: &CaptionEvent) -> bool {
+    is_selected_channel(args, caption.channel)
+}
+
+fn is_selected_channel(args: &Args, channel: CaptionChannel) -> bool {
+    args.channels.is_empty() || args.channels.contains(&channel)
+}
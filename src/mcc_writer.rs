@@ -0,0 +1,357 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use anyhow::{Result, anyhow};
+use uuid::Uuid;
+use crate::caption_event::CaptionTime;
+use crate::cea708::CaptionData;
+use crate::timecode::{FrameRate, FrameRateDetector};
+
+// Descriptive header that the format requires in every generated file
+const MCC_HEADER: &str = "File Format=MacCaption_MCC V2.0
+
+///////////////////////////////////////////////////////////////////////////////////
+// Computer Prompting and Captioning Company
+// Ancillary Data Packet Transfer File
+//
+// Permission to generate this format is granted provided that
+//   1. This ANC Transfer file format is used on an as-is basis and no warranty is given, and
+//   2. This entire descriptive information text is included in a generated .mcc file.
+//
+// General file format:
+//   HH:MM:SS:FF(tab)[Hexadecimal ANC data in groups of 2 characters]
+//     Hexadecimal data starts with the Ancillary Data Packet DID (Data ID defined in S291M)
+//       and concludes with the Check Sum following the User Data Words.
+//     Each time code line must contain at most one complete ancillary data packet.
+//     To transfer additional ANC Data successive lines may contain identical time code.
+//     Time Code Rate=[24, 25, 30, 30DF, 50, 60]
+//
+//   ANC data bytes may be represented by one ASCII character according to the following schema:
+//     G  FAh 00h 00h
+//     H  2 x (FAh 00h 00h)
+//     I  3 x (FAh 00h 00h)
+//     J  4 x (FAh 00h 00h)
+//     K  5 x (FAh 00h 00h)
+//     L  6 x (FAh 00h 00h)
+//     M  7 x (FAh 00h 00h)
+//     N  8 x (FAh 00h 00h)
+//     O  9 x (FAh 00h 00h)
+//     P  FBh 80h 80h
+//     Q  FCh 80h 80h
+//     R  FDh 80h 80h
+//     S  96h 69h
+//     T  61h 01h
+//     U  E1h 00h 00h 00h
+//     Z  00h
+//
+///////////////////////////////////////////////////////////////////////////////////";
+
+// SMPTE 291 ancillary packet carrying a SMPTE 334 caption distribution packet
+const ANC_DID: u8 = 0x61;
+const ANC_SDID: u8 = 0x01;
+const CDP_IDENTIFIER: [u8; 2] = [0x96, 0x69];
+const CDP_CCDATA_ID: u8 = 0x72;
+const CDP_FOOTER_ID: u8 = 0x74;
+// ccdata_present, caption_service_active and the reserved bit
+const CDP_FLAGS: u8 = 0x43;
+// cc_valid = 0, cc_type = 2 (DTVCC padding)
+const PADDING_TRIPLET: [u8; 3] = [0xFA, 0x00, 0x00];
+
+// Byte sequences the MCC format abbreviates to a single letter, longest first
+const ABBREVIATIONS: [(&[u8], char); 7] = [
+    (&[0xE1, 0x00, 0x00, 0x00], 'U'),
+    (&[0xFB, 0x80, 0x80], 'P'),
+    (&[0xFC, 0x80, 0x80], 'Q'),
+    (&[0xFD, 0x80, 0x80], 'R'),
+    (&[0x96, 0x69], 'S'),
+    (&[0x61, 0x01], 'T'),
+    (&[0x00], 'Z'),
+];
+
+// Writes the valid cc_data of every frame, CEA-608 field pairs and DTVCC
+// packet data alike, as MacCaption MCC ancillary data. Each frame becomes one
+// caption distribution packet (CDP) on a timecode line. The CDP is rebuilt
+// from the parsed triplets, so invalid triplets from the stream are gone and
+// the padding is new.
+pub struct MccWriter {
+    output: BufWriter<File>,
+    frame_rate: FrameRateDetector<Vec<CaptionData>>,
+    header_written: bool,
+    sequence_counter: u16,
+}
+
+impl MccWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .map_err(|e| anyhow!("Unable to create {}: {}", path.display(), e))?;
+        
+        Ok(Self {
+            output: BufWriter::new(file),
+            frame_rate: FrameRateDetector::new(),
+            header_written: false,
+            sequence_counter: 0,
+        })
+    }
+
+    // Takes the cc_data of each frame in presentation order
+    pub fn write_cc_data(&mut self, frames: Vec<(CaptionTime, Vec<CaptionData>)>) -> Result<()> {
+        let frames = self.frame_rate.push(frames);
+        self.write_frames(frames)
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        let frames = self.frame_rate.finish(FrameRate::NTSC);
+        self.write_frames(frames)?;
+        self.output.flush()?;
+        Ok(())
+    }
+
+    fn write_frames(&mut self, frames: Vec<(CaptionTime, Vec<CaptionData>)>) -> Result<()> {
+        let Some(frame_rate) = self.frame_rate.frame_rate() else {
+            return Ok(());
+        };
+        
+        // The header names the timecode rate, so it waits for the first frames
+        if !self.header_written {
+            self.write_header(frame_rate)?;
+            self.header_written = true;
+        }
+        
+        let timecode_rate = timecode_rate(frame_rate);
+        for (time, caption_data) in frames {
+            let cdp = build_cdp(frame_rate, self.sequence_counter, &caption_data);
+            self.sequence_counter = self.sequence_counter.wrapping_add(1);
+            
+            let mut packet = vec![ANC_DID, ANC_SDID, cdp.len() as u8];
+            packet.extend_from_slice(&cdp);
+            let checksum = packet.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            packet.push(checksum);
+            
+            let frame = frame_rate.frame_number(time.seconds) * timecode_rate.nominal as u64 / frame_rate.nominal as u64;
+            let timecode = timecode_rate.timecode(frame);
+            writeln!(self.output, "{}\t{}", timecode, abbreviate(&packet))?;
+        }
+        self.output.flush()?;
+        Ok(())
+    }
+
+    fn write_header(&mut self, frame_rate: FrameRate) -> Result<()> {
+        let timecode_rate = timecode_rate(frame_rate);
+        let time_code_rate = if timecode_rate.drop_frame() {
+            format!("{}DF", timecode_rate.nominal)
+        } else {
+            timecode_rate.nominal.to_string()
+        };
+        
+        writeln!(self.output, "{}", MCC_HEADER)?;
+        writeln!(self.output)?;
+        writeln!(self.output, "UUID={}", Uuid::new_v4().to_string().to_uppercase())?;
+        writeln!(self.output, "Creation Program={} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))?;
+        writeln!(self.output, "Time Code Rate={}", time_code_rate)?;
+        writeln!(self.output)?;
+        Ok(())
+    }
+}
+
+// SMPTE 334-2 CDP with a ccdata_section padded to the cc_count of the frame
+// rate
+fn build_cdp(frame_rate: FrameRate, sequence_counter: u16, caption_data: &[CaptionData]) -> Vec<u8> {
+    let (rate_code, cc_count) = cdp_frame_rate(frame_rate);
+    let sequence = sequence_counter.to_be_bytes();
+    
+    let mut triplets: Vec<[u8; 3]> = caption_data
+        .iter()
+        .map(|data| [0xF8 | (data.cc_valid as u8) << 2 | data.cc_type, data.cc_data[0], data.cc_data[1]])
+        .collect();
+    triplets.truncate(0x1F);
+    if triplets.len() < cc_count {
+        triplets.resize(cc_count, PADDING_TRIPLET);
+    }
+    
+    let mut cdp = CDP_IDENTIFIER.to_vec();
+    cdp.push(0);
+    cdp.push(rate_code << 4 | 0x0F);
+    cdp.push(CDP_FLAGS);
+    cdp.extend_from_slice(&sequence);
+    cdp.push(CDP_CCDATA_ID);
+    cdp.push(0xE0 | triplets.len() as u8);
+    for triplet in &triplets {
+        cdp.extend_from_slice(triplet);
+    }
+    cdp.push(CDP_FOOTER_ID);
+    cdp.extend_from_slice(&sequence);
+    
+    // cdp_length counts the whole packet including the checksum byte
+    cdp[2] = (cdp.len() + 1) as u8;
+    let sum = cdp.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    cdp.push(sum.wrapping_neg());
+    cdp
+}
+
+// cdp_frame_rate code and the number of cc_data triplets per frame
+fn cdp_frame_rate(frame_rate: FrameRate) -> (u8, usize) {
+    match (frame_rate.nominal, frame_rate.fractional) {
+        (24, true) => (1, 25),
+        (24, false) => (2, 25),
+        (25, _) => (3, 24),
+        (30, true) => (4, 20),
+        (30, false) => (5, 20),
+        (50, _) => (6, 12),
+        (60, true) => (7, 10),
+        _ => (8, 10),
+    }
+}
+
+fn abbreviate(packet: &[u8]) -> String {
+    let mut text = String::new();
+    let mut rest = packet;
+    
+    while !rest.is_empty() {
+        // Runs of DTVCC padding become G (one) through O (nine)
+        let padding = rest
+            .chunks_exact(3)
+            .take(9)
+            .take_while(|triplet| *triplet == PADDING_TRIPLET)
+            .count();
+        if padding > 0 {
+            text.push((b'F' + padding as u8) as char);
+            rest = &rest[padding * 3..];
+            continue;
+        }
+        
+        match ABBREVIATIONS.iter().find(|(bytes, _)| rest.starts_with(bytes)) {
+            Some((bytes, letter)) => {
+                text.push(*letter);
+                rest = &rest[bytes.len()..];
+            }
+            None => {
+                text.push_str(&format!("{:02X}", rest[0]));
+                rest = &rest[1..];
+            }
+        }
+    }
+    text
+}
+
+// Rate of the timecode lines. MCC has no drop-frame rate above 30, so 59.94
+// fps frames are numbered in pairs on 30DF timecode, two lines to a timecode.
+fn timecode_rate(frame_rate: FrameRate) -> FrameRate {
+    if frame_rate.drop_frame() {
+        frame_rate.halved()
+    } else {
+        frame_rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cea608(data1: u8, data2: u8) -> CaptionData {
+        CaptionData {
+            cc_valid: true,
+            cc_type: 0,
+            cc_data: [data1, data2],
+        }
+    }
+
+    // Writes frame_count frames of padding at the given rate
+    fn write(name: &str, frame_rate: FrameRate, frame_count: u64) -> String {
+        let path = std::env::temp_dir().join(format!("hlscaptionfinder-{}-{}", std::process::id(), name));
+        let frames = (0..frame_count)
+            .map(|frame| {
+                let seconds = frame as f64 / frame_rate.fps();
+                let time = CaptionTime { pts: (seconds * 90000.0).round() as u64, seconds };
+                (time, vec![cea608(0x80, 0x80)])
+            })
+            .collect();
+        let mut writer = MccWriter::create(&path).unwrap();
+        writer.write_cc_data(frames).unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+        
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        contents
+    }
+
+    fn header_value<'a>(mcc: &'a str, key: &str) -> &'a str {
+        mcc.lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+            .unwrap()
+    }
+
+    fn timecodes(mcc: &str) -> Vec<&str> {
+        mcc.lines()
+            .filter_map(|line| line.split_once('\t'))
+            .map(|(timecode, _)| timecode)
+            .collect()
+    }
+
+    #[test]
+    fn time_code_rate_is_one_the_format_lists() {
+        let cases = [
+            (FrameRate { nominal: 24, fractional: true }, "24"),
+            (FrameRate { nominal: 25, fractional: false }, "25"),
+            (FrameRate::NTSC, "30DF"),
+            (FrameRate { nominal: 30, fractional: false }, "30"),
+            (FrameRate { nominal: 50, fractional: false }, "50"),
+            (FrameRate { nominal: 60, fractional: true }, "30DF"),
+            (FrameRate { nominal: 60, fractional: false }, "60"),
+        ];
+        for (frame_rate, expected) in cases {
+            let mcc = write(&format!("rate-{}.mcc", expected), frame_rate, 3);
+            assert_eq!(header_value(&mcc, "Time Code Rate"), expected, "{:?}", frame_rate);
+        }
+    }
+
+    #[test]
+    fn frames_at_59_94_share_30df_timecodes_in_pairs() {
+        let mcc = write("59.94.mcc", FrameRate { nominal: 60, fractional: true }, 5);
+        
+        assert_eq!(timecodes(&mcc), ["00:00:00;00", "00:00:00;00", "00:00:00;01", "00:00:00;01", "00:00:00;02"]);
+    }
+
+    #[test]
+    fn uuid_is_random_version_4() {
+        let first = write("uuid-1.mcc", FrameRate::NTSC, 2);
+        let second = write("uuid-2.mcc", FrameRate::NTSC, 2);
+        
+        let uuid = Uuid::parse_str(header_value(&first, "UUID")).unwrap();
+        assert_eq!(uuid.get_version_num(), 4);
+        assert_ne!(header_value(&first, "UUID"), header_value(&second, "UUID"));
+    }
+
+    #[test]
+    fn cdp_checksum_zeroes_the_packet_sum() {
+        for caption_data in [vec![], vec![cea608(0x94, 0x2C), cea608(0xC8, 0xC9)]] {
+            let cdp = build_cdp(FrameRate::NTSC, 0x1234, &caption_data);
+            assert_eq!(cdp.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)), 0);
+            assert_eq!(cdp[2] as usize, cdp.len());
+        }
+    }
+
+    #[test]
+    fn cdp_carries_cc_data_padded_to_frame_rate() {
+        let cdp = build_cdp(FrameRate::NTSC, 0x1234, &[cea608(0x94, 0x2C)]);
+        
+        assert_eq!(cdp[..2], CDP_IDENTIFIER);
+        assert_eq!(cdp[3], 0x4F);
+        assert_eq!(cdp[5..7], [0x12, 0x34]);
+        assert_eq!(cdp[7], CDP_CCDATA_ID);
+        // 29.97 fps frames carry 20 triplets
+        assert_eq!(cdp[8], 0xE0 | 20);
+        assert_eq!(cdp[9..12], [0xFC, 0x94, 0x2C]);
+        assert!(cdp[12..69].chunks(3).all(|triplet| triplet == PADDING_TRIPLET));
+        assert_eq!(cdp[69..72], [CDP_FOOTER_ID, 0x12, 0x34]);
+        assert_eq!(cdp.len(), 73);
+    }
+
+    #[test]
+    fn abbreviates_padding_and_common_sequences() {
+        let mut packet = vec![ANC_DID, ANC_SDID, 0x00];
+        packet.extend_from_slice(&PADDING_TRIPLET.repeat(11));
+        packet.extend_from_slice(&[0xFC, 0x80, 0x80, 0x2A]);
+        assert_eq!(abbreviate(&packet), "TZOHQ2A");
+    }
+}
@@ -0,0 +1,338 @@
+use std::borrow::Cow;
+
+const STREAM_TYPE_MPEG1_VIDEO: u8 = 0x01;
+const STREAM_TYPE_MPEG2_VIDEO: u8 = 0x02;
+const STREAM_TYPE_H264: u8 = 0x1B;
+const STREAM_TYPE_H265: u8 = 0x24;
+// H.264 with SAMPLE-AES encrypted slices (Apple HLS sample encryption)
+const STREAM_TYPE_H264_SAMPLE_AES: u8 = 0xDB;
+
+const H264_NAL_SEI: u8 = 6;
+const HEVC_NAL_PREFIX_SEI: u8 = 39;
+const HEVC_NAL_SUFFIX_SEI: u8 = 40;
+const SEI_USER_DATA_REGISTERED_ITU_T_T35: u32 = 4;
+const MPEG2_USER_DATA_START_CODE: u8 = 0xB2;
+
+// The video codecs captions are extracted from, which decides how the
+// elementary stream is cut into units and where the caption data sits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoCodec {
+    Mpeg2,
+    H264,
+    Hevc,
+}
+
+impl VideoCodec {
+    pub fn from_stream_type(stream_type: u8) -> Option<Self> {
+        match stream_type {
+            STREAM_TYPE_MPEG1_VIDEO | STREAM_TYPE_MPEG2_VIDEO => Some(VideoCodec::Mpeg2),
+            // SAMPLE-AES only encrypts slice data; SEI NAL units stay in
+            // the clear and are read like any other H.264 stream
+            STREAM_TYPE_H264 | STREAM_TYPE_H264_SAMPLE_AES => Some(VideoCodec::H264),
+            STREAM_TYPE_H265 => Some(VideoCodec::Hevc),
+            _ => None,
+        }
+    }
+}
+
+// Caption carrying payload of one unit, still in its container format
+pub enum CaptionPayload<'a> {
+    // user_data_registered_itu_t_t35 SEI message (ATSC A/53 and friends),
+    // with emulation prevention bytes removed
+    ItuT35(Cow<'a, [u8]>),
+    // MPEG-2 picture user data (A/53, SCTE-20 or DVD)
+    Mpeg2UserData(&'a [u8]),
+}
+
+// Cuts an Annex B bytestream into NAL units at their start codes, in one
+// pass over data that is known to be complete, such as the access units of
+// a PES packet. MPEG-2 has no NAL units, but its start codes delimit the
+// stream the same way, so it is cut into start code units. Units come
+// without their start code; data before the first start code is skipped.
+pub struct AnnexBUnits<'a> {
+    // Starts at the first byte of the next unit
+    remaining: &'a [u8],
+}
+
+pub fn annex_b_units(data: &[u8]) -> AnnexBUnits<'_> {
+    let remaining = match find_start_code(data) {
+        Some((start, start_code_len)) => &data[start + start_code_len..],
+        None => &[],
+    };
+    AnnexBUnits { remaining }
+}
+
+impl<'a> Iterator for AnnexBUnits<'a> {
+    type Item = &'a [u8];
+    
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.remaining.is_empty() {
+            return None;
+        }
+        
+        let unit = match find_start_code(self.remaining) {
+            Some((unit_length, start_code_len)) => {
+                let unit = &self.remaining[..unit_length];
+                self.remaining = &self.remaining[unit_length + start_code_len..];
+                unit
+            }
+            None => std::mem::take(&mut self.remaining),
+        };
+        Some(unit)
+    }
+}
+
+fn find_start_code(data: &[u8]) -> Option<(usize, usize)> {
+    // Use optimized scanning - look for 0x00 first, then verify pattern
+    let mut i = 0;
+    while i + 2 < data.len() {
+        // Quick scan for 0x00 0x00 pattern
+        if data[i] == 0x00 && data[i + 1] == 0x00 {
+            // Check for 4-byte start code
+            if i + 3 < data.len() && data[i + 2] == 0x00 && data[i + 3] == 0x01 {
+                return Some((i, 4));
+            }
+            // Check for 3-byte start code
+            if data[i + 2] == 0x01 {
+                return Some((i, 3));
+            }
+            i += 2; // Skip past this 0x00 0x00 sequence
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+// The caption payloads of a NAL unit (or MPEG-2 start code unit). Only SEI
+// NAL units and MPEG-2 user data can carry captions.
+pub fn caption_payloads(codec: VideoCodec, unit: &[u8]) -> Vec<CaptionPayload<'_>> {
+    if codec == VideoCodec::Mpeg2 {
+        return match unit.split_first() {
+            Some((&MPEG2_USER_DATA_START_CODE, user_data)) => vec![CaptionPayload::Mpeg2UserData(user_data)],
+            _ => Vec::new(),
+        };
+    }
+
+    // SEI message sizes count RBSP bytes, so emulation prevention bytes are
+    // removed before the messages are split
+    match sei_header_len(codec, unit) {
+        Some(header_len) => match remove_emulation_prevention(&unit[header_len..]) {
+            Cow::Borrowed(rbsp) => sei_t35_payloads(rbsp)
+                .into_iter()
+                .map(|payload| CaptionPayload::ItuT35(Cow::Borrowed(payload)))
+                .collect(),
+            Cow::Owned(rbsp) => sei_t35_payloads(&rbsp)
+                .into_iter()
+                .map(|payload| CaptionPayload::ItuT35(Cow::Owned(payload.to_vec())))
+                .collect(),
+        },
+        None => Vec::new(),
+    }
+}
+
+// Turns a NAL unit payload into its RBSP. Encoders insert an emulation
+// prevention byte (0x03) after every two zero bytes that would otherwise be
+// followed by 0x00-0x03, so that the payload never contains a start code.
+// Most units have none, and those are borrowed as they are.
+fn remove_emulation_prevention(data: &[u8]) -> Cow<'_, [u8]> {
+    if !data.windows(3).any(|window| window == [0x00, 0x00, 0x03]) {
+        return Cow::Borrowed(data);
+    }
+    
+    let mut rbsp = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+    for &byte in data {
+        if zeros >= 2 && byte == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0x00 { zeros + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+    Cow::Owned(rbsp)
+}
+
+// Returns the NAL header length if the NALU is an SEI. H.264 uses a one-byte
+// header with type 6; HEVC uses a two-byte header with prefix SEI 39 and
+// suffix SEI 40.
+fn sei_header_len(codec: VideoCodec, unit: &[u8]) -> Option<usize> {
+    if codec == VideoCodec::Hevc {
+        if unit.len() < 2 {
+            return None;
+        }
+        let nalu_type = (unit[0] >> 1) & 0x3F;
+        if nalu_type == HEVC_NAL_PREFIX_SEI || nalu_type == HEVC_NAL_SUFFIX_SEI {
+            return Some(2);
+        }
+        return None;
+    }
+    
+    let nalu_type = unit.first()? & 0x1F;
+    if nalu_type == H264_NAL_SEI {
+        Some(1)
+    } else {
+        None
+    }
+}
+
+// The user_data_registered_itu_t_t35 messages of an SEI RBSP
+fn sei_t35_payloads(data: &[u8]) -> Vec<&[u8]> {
+    let mut payloads = Vec::new();
+    let mut i = 0;
+    
+    // Parse SEI messages
+    while i + 1 < data.len() {
+        // Parse payload type (with 0xFF escaping)
+        let mut payload_type = 0u32;
+        while i < data.len() && data[i] == 0xFF {
+            payload_type += 255;
+            i += 1;
+        }
+        
+        if i >= data.len() {
+            break;
+        }
+        
+        payload_type += data[i] as u32;
+        i += 1;
+        
+        // Parse payload size (with 0xFF escaping)
+        let mut payload_size = 0u32;
+        while i < data.len() && data[i] == 0xFF {
+            payload_size += 255;
+            i += 1;
+        }
+        
+        if i >= data.len() {
+            break;
+        }
+        
+        payload_size += data[i] as u32;
+        i += 1;
+        
+        if payload_type == SEI_USER_DATA_REGISTERED_ITU_T_T35 && payload_size > 0 && i + payload_size as usize <= data.len() {
+            payloads.push(&data[i..i + payload_size as usize]);
+        }
+        
+        i += payload_size as usize;
+    }
+    
+    payloads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ATSC A/53 header of a user_data_registered_itu_t_t35 payload: country
+    // code, provider code, "GA94" and the cc_data user_data_type_code
+    const GA94_HEADER: [u8; 8] = [0xB5, 0x00, 0x31, b'G', b'A', b'9', b'4', 0x03];
+    const RBSP_STOP_BIT: u8 = 0x80;
+
+    // Inserts emulation prevention bytes the way an encoder does
+    fn escape(rbsp: &[u8]) -> Vec<u8> {
+        let mut data = Vec::with_capacity(rbsp.len());
+        let mut zeros = 0;
+        for &byte in rbsp {
+            if zeros >= 2 && byte <= 0x03 {
+                data.push(0x03);
+                zeros = 0;
+            }
+            zeros = if byte == 0x00 { zeros + 1 } else { 0 };
+            data.push(byte);
+        }
+        data
+    }
+
+    fn sei_message(payload_type: u8, payload: &[u8]) -> Vec<u8> {
+        let mut message = vec![payload_type, payload.len() as u8];
+        message.extend_from_slice(payload);
+        message
+    }
+
+    fn ga94_payload(cc_data: &[u8]) -> Vec<u8> {
+        let mut payload = GA94_HEADER.to_vec();
+        payload.extend_from_slice(cc_data);
+        payload
+    }
+
+    fn h264_sei(escaped_rbsp: &[u8]) -> Vec<u8> {
+        let mut unit = vec![H264_NAL_SEI];
+        unit.extend_from_slice(escaped_rbsp);
+        unit
+    }
+
+    fn t35_payloads(unit: &[u8]) -> Vec<Vec<u8>> {
+        caption_payloads(VideoCodec::H264, unit)
+            .into_iter()
+            .map(|payload| match payload {
+                CaptionPayload::ItuT35(data) => data.into_owned(),
+                CaptionPayload::Mpeg2UserData(_) => panic!("H.264 units carry no MPEG-2 user data"),
+            })
+            .collect()
+    }
+
+    fn contains(data: &[u8], pattern: &[u8]) -> bool {
+        data.windows(pattern.len()).any(|window| window == pattern)
+    }
+
+    #[test]
+    fn emulation_prevention_inside_cc_data() {
+        // A DTVCC triplet with zero data is followed by a padding triplet
+        // whose marker byte is 0x02, which the encoder escapes
+        let payload = ga94_payload(&[
+            0x43, 0xFF,
+            0xFE, 0x00, 0x00,
+            0x02, 0x00, 0x00,
+            0xFC, 0x94, 0x2C,
+            0xFF,
+        ]);
+        let mut rbsp = sei_message(SEI_USER_DATA_REGISTERED_ITU_T_T35 as u8, &payload);
+        rbsp.push(RBSP_STOP_BIT);
+        let unit = h264_sei(&escape(&rbsp));
+        assert!(contains(&unit, &[0xFE, 0x00, 0x00, 0x03, 0x02]));
+        
+        assert_eq!(t35_payloads(&unit), vec![payload]);
+    }
+
+    #[test]
+    fn emulation_prevention_in_payload_type_and_size() {
+        // The zero bytes that end one message run into the type or size
+        // bytes of the next, so those get escaped
+        let mut unregistered = vec![0x11; 16];
+        unregistered.push(0x00);
+        let payload = ga94_payload(&[0x41, 0xFF, 0xFC, 0x94, 0x2C, 0xFF]);
+        
+        let mut rbsp = sei_message(5, &unregistered);
+        rbsp.extend(sei_message(0, &[0x00, 0x00]));
+        rbsp.extend(sei_message(1, &[0x80]));
+        rbsp.extend(sei_message(SEI_USER_DATA_REGISTERED_ITU_T_T35 as u8, &payload));
+        rbsp.push(RBSP_STOP_BIT);
+        let unit = h264_sei(&escape(&rbsp));
+        // Before the size of the buffering period message and the type of
+        // the picture timing message
+        assert!(contains(&unit, &[0x00, 0x00, 0x03, 0x02, 0x00, 0x00, 0x03, 0x01]));
+        
+        assert_eq!(t35_payloads(&unit), vec![payload]);
+    }
+
+    #[test]
+    fn emulation_prevention_at_end_of_unit() {
+        // An RBSP ending in zero bytes gets a trailing 0x03 so that the next
+        // start code is not mistaken for part of it
+        let payload = ga94_payload(&[0x41, 0xFF, 0xFE, 0x00, 0x00]);
+        let rbsp = sei_message(SEI_USER_DATA_REGISTERED_ITU_T_T35 as u8, &payload);
+        let mut unit = h264_sei(&escape(&rbsp));
+        unit.push(0x03);
+        assert!(unit.ends_with(&[0xFE, 0x00, 0x00, 0x03]));
+        
+        assert_eq!(t35_payloads(&unit), vec![payload]);
+    }
+
+    #[test]
+    fn units_without_emulation_prevention_are_borrowed() {
+        let data = [0x04, 0x02, 0x00, 0x00, RBSP_STOP_BIT];
+        assert!(matches!(remove_emulation_prevention(&data), Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::BTreeMap;
+use log::debug;
+
+// PTS values are 33 bits and wrap roughly every 26.5 hours
+pub const PTS_WRAP: u64 = 1 << 33;
+const PES_START_CODE: [u8; 3] = [0x00, 0x00, 0x01];
+// Start code, stream_id and PES_packet_length
+const PES_PREFIX_SIZE: usize = 6;
+const PES_HEADER_SIZE: usize = 9;
+
+// Streams whose PES packets have no optional header (and so no timestamps)
+const STREAM_ID_PROGRAM_STREAM_MAP: u8 = 0xBC;
+const STREAM_ID_PADDING: u8 = 0xBE;
+const STREAM_ID_PRIVATE_STREAM_2: u8 = 0xBF;
+const STREAM_ID_ECM: u8 = 0xF0;
+const STREAM_ID_EMM: u8 = 0xF1;
+const STREAM_ID_DSMCC: u8 = 0xF2;
+const STREAM_ID_H222_TYPE_E: u8 = 0xF8;
+const STREAM_ID_PROGRAM_STREAM_DIRECTORY: u8 = 0xFF;
+
+// The fields of a PES packet header that frame and time the access units
+// it carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PesHeader {
+    pub stream_id: u8,
+    // Size of the whole PES packet from PES_packet_length, or None when the
+    // length is left unbounded (0), as video streams in TS may do
+    pub packet_size: Option<usize>,
+    pub pts: Option<u64>,
+    pub dts: Option<u64>,
+    // Bytes before the elementary stream data, including stuffing
+    pub header_size: usize,
+}
+
+impl PesHeader {
+    // Parses the header at the start of a PES packet
+    pub fn parse(data: &[u8]) -> Option<PesHeader> {
+        if data.len() < PES_PREFIX_SIZE || data[..3] != PES_START_CODE {
+            return None;
+        }
+        
+        let stream_id = data[3];
+        let packet_length = u16::from_be_bytes([data[4], data[5]]) as usize;
+        let packet_size = (packet_length > 0).then_some(PES_PREFIX_SIZE + packet_length);
+        
+        if !has_optional_header(stream_id) {
+            return Some(PesHeader {
+                stream_id,
+                packet_size,
+                pts: None,
+                dts: None,
+                header_size: PES_PREFIX_SIZE,
+            });
+        }
+        
+        if data.len() < PES_HEADER_SIZE {
+            return None;
+        }
+        
+        let pts_dts_flags = data[7] >> 6;
+        let pts = if pts_dts_flags & 0b10 != 0 {
+            data.get(PES_HEADER_SIZE..PES_HEADER_SIZE + 5).map(parse_timestamp)
+        } else {
+            None
+        };
+        let dts = if pts_dts_flags == 0b11 {
+            data.get(PES_HEADER_SIZE + 5..PES_HEADER_SIZE + 10).map(parse_timestamp)
+        } else {
+            None
+        };
+        
+        Some(PesHeader {
+            stream_id,
+            packet_size,
+            pts,
+            dts,
+            header_size: PES_HEADER_SIZE + data[8] as usize,
+        })
+    }
+}
+
+fn has_optional_header(stream_id: u8) -> bool {
+    !matches!(
+        stream_id,
+        STREAM_ID_PROGRAM_STREAM_MAP
+            | STREAM_ID_PADDING
+            | STREAM_ID_PRIVATE_STREAM_2
+            | STREAM_ID_ECM
+            | STREAM_ID_EMM
+            | STREAM_ID_DSMCC
+            | STREAM_ID_H222_TYPE_E
+            | STREAM_ID_PROGRAM_STREAM_DIRECTORY
+    )
+}
+
+fn parse_timestamp(data: &[u8]) -> u64 {
+    let mut pts = 0u64;
+    pts |= (data[0] as u64 & 0x0E) << 29;
+    pts |= (data[1] as u64 & 0xFF) << 22;
+    pts |= (data[2] as u64 & 0xFE) << 14;
+    pts |= (data[3] as u64 & 0xFF) << 7;
+    pts |= (data[4] as u64 & 0xFE) >> 1;
+    pts
+}
+
+// A complete PES packet. For video this holds whole access units, timed by
+// the header's PTS and DTS.
+pub struct PesPacket {
+    pub pid: u16,
+    pub header: PesHeader,
+    // Elementary stream data, after the header
+    pub data: Vec<u8>,
+}
+
+impl PesPacket {
+    fn new(pid: u16, mut data: Vec<u8>) -> Option<Self> {
+        let Some(header) = PesHeader::parse(&data) else {
+            debug!("Dropping {} bytes on PID {} that do not start with a PES header", data.len(), pid);
+            return None;
+        };
+        
+        if let Some(packet_size) = header.packet_size {
+            if data.len() < packet_size {
+                debug!("PES packet on PID {} ended after {} of {} bytes", pid, data.len(), packet_size);
+            }
+            data.truncate(packet_size);
+        }
+        data.drain(..header.header_size.min(data.len()));
+        
+        Some(PesPacket { pid, header, data })
+    }
+}
+
+// Reassembles the PES packets of each PID from transport stream packet
+// payloads. A PES packet ends once PES_packet_length bytes have arrived, or
+// when its length is unbounded, where the next one starts.
+#[derive(Default)]
+pub struct PesAssembler {
+    // Data of the PES packet in progress on each PID, from its start code
+    partial: BTreeMap<u16, Vec<u8>>,
+}
+
+impl PesAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Takes the payload of one TS packet and returns the PES packets it
+    // completes: the previous one on the PID when a new one starts, and a
+    // bounded one once all of it has arrived
+    pub fn push(&mut self, pid: u16, unit_start: bool, data: &[u8]) -> Vec<PesPacket> {
+        let mut packets = Vec::new();
+        
+        // Packets that only carry an adaptation field have no PES data
+        if data.is_empty() {
+            return packets;
+        }
+        
+        if unit_start {
+            packets.extend(self.finish(pid));
+            self.partial.insert(pid, data.to_vec());
+        } else if let Some(partial) = self.partial.get_mut(&pid) {
+            partial.extend_from_slice(data);
+        } else {
+            // The stream was joined in the middle of a PES packet
+            return packets;
+        }
+        
+        // Anything after the end of a bounded packet is stuffing
+        let complete = self.partial.get(&pid).is_some_and(|partial| {
+            pes_packet_size(partial).is_some_and(|packet_size| partial.len() >= packet_size)
+        });
+        if complete {
+            packets.extend(self.finish(pid));
+        }
+        
+        packets
+    }
+
+    // Ends the PES packets in progress, at the end of the data or where
+    // the stream is discontinuous
+    pub fn flush(&mut self) -> Vec<PesPacket> {
+        std::mem::take(&mut self.partial)
+            .into_iter()
+            .filter_map(|(pid, data)| PesPacket::new(pid, data))
+            .collect()
+    }
+
+    fn finish(&mut self, pid: u16) -> Option<PesPacket> {
+        let data = self.partial.remove(&pid)?;
+        PesPacket::new(pid, data)
+    }
+}
+
+// PES_packet_length, once enough of the packet has arrived to read it
+fn pes_packet_size(data: &[u8]) -> Option<usize> {
+    let packet_length = u16::from_be_bytes([*data.get(4)?, *data.get(5)?]) as usize;
+    (packet_length > 0).then_some(PES_PREFIX_SIZE + packet_length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIDEO_STREAM_ID: u8 = 0xE0;
+
+    fn timestamp(prefix: u8, value: u64) -> [u8; 5] {
+        [
+            prefix << 4 | ((value >> 29) & 0x0E) as u8 | 1,
+            (value >> 22) as u8,
+            ((value >> 14) & 0xFE) as u8 | 1,
+            (value >> 7) as u8,
+            ((value << 1) & 0xFE) as u8 | 1,
+        ]
+    }
+
+    // A video PES packet with PTS and DTS; PES_packet_length is left at 0
+    // unless bounded
+    fn pes_packet(pts: u64, dts: u64, payload: &[u8], bounded: bool) -> Vec<u8> {
+        let mut header = vec![0x80, 0xC0, 10];
+        header.extend_from_slice(&timestamp(0b0011, pts));
+        header.extend_from_slice(&timestamp(0b0001, dts));
+        
+        let packet_length = if bounded { header.len() + payload.len() } else { 0 };
+        let mut packet = PES_START_CODE.to_vec();
+        packet.push(VIDEO_STREAM_ID);
+        packet.extend_from_slice(&(packet_length as u16).to_be_bytes());
+        packet.extend(header);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    #[test]
+    fn parses_pts_and_dts() {
+        let pts = PTS_WRAP - 3003;
+        let header = PesHeader::parse(&pes_packet(pts, 900000, b"", true)).unwrap();
+        assert_eq!(header.stream_id, VIDEO_STREAM_ID);
+        assert_eq!(header.pts, Some(pts));
+        assert_eq!(header.dts, Some(900000));
+        assert_eq!(header.header_size, 19);
+        assert_eq!(header.packet_size, Some(19));
+    }
+
+    #[test]
+    fn completes_bounded_packet_once_all_of_it_arrived() {
+        let mut assembler = PesAssembler::new();
+        let packet = pes_packet(3003, 0, b"access unit", true);
+        let (first, rest) = packet.split_at(16);
+        
+        assert!(assembler.push(0x100, true, first).is_empty());
+        // Stuffing after the end of the packet is dropped
+        let mut last = rest.to_vec();
+        last.extend_from_slice(&[0xFF; 4]);
+        let packets = assembler.push(0x100, false, &last);
+        
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].pid, 0x100);
+        assert_eq!(packets[0].header.pts, Some(3003));
+        assert_eq!(packets[0].data, b"access unit");
+        assert!(assembler.flush().is_empty());
+    }
+
+    #[test]
+    fn completes_unbounded_packet_when_next_one_starts() {
+        let mut assembler = PesAssembler::new();
+        let first = pes_packet(3003, 0, b"first", false);
+        let second = pes_packet(6006, 3003, b"second", false);
+        
+        assert!(assembler.push(0x100, true, &first[..10]).is_empty());
+        assert!(assembler.push(0x100, false, &first[10..]).is_empty());
+        // Another PID does not end it
+        assert!(assembler.push(0x101, true, &pes_packet(0, 0, b"other", false)).is_empty());
+        
+        let packets = assembler.push(0x100, true, &second);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].data, b"first");
+        
+        let mut flushed = assembler.flush();
+        flushed.sort_by_key(|packet| packet.pid);
+        let data: Vec<_> = flushed.iter().map(|packet| (packet.pid, packet.data.as_slice())).collect();
+        assert_eq!(data, vec![(0x100, &b"second"[..]), (0x101, &b"other"[..])]);
+    }
+
+    #[test]
+    fn drops_data_before_first_packet_start() {
+        let mut assembler = PesAssembler::new();
+        assert!(assembler.push(0x100, false, b"middle of a packet").is_empty());
+        assert!(assembler.flush().is_empty());
+    }
+}
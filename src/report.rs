@@ -0,0 +1,466 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use log::{debug, info};
+use serde::Serialize;
+use hlscaptionfinder::caption_event::{CaptionChannel, CaptionEvent};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    // Human readable progress and captions
+    Text,
+    // One JSON document once the whole playlist has been processed
+    Json,
+    // One JSON event per line as segments are processed
+    Ndjson,
+}
+
+// What was found in one segment
+#[derive(Debug, Serialize)]
+pub struct SegmentReport {
+    pub uri: String,
+    pub sequence_number: u64,
+    pub start_time: f64,
+    pub duration: f64,
+    pub channels: Vec<CaptionChannel>,
+    pub services: Vec<u8>,
+    // Caption data for a selected channel was decoded in the segment, even
+    // if no caption ended in it
+    #[serde(skip)]
+    pub carries_captions: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub captions: Vec<CaptionEvent>,
+}
+
+#[derive(Debug, Serialize)]
+struct Summary {
+    segments: usize,
+    segments_with_captions: usize,
+    captions: usize,
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    input: &'a str,
+    segments: &'a [SegmentReport],
+    summary: Summary,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    SegmentProcessed {
+        uri: &'a str,
+        sequence_number: u64,
+        start_time: f64,
+        duration: f64,
+        channels: &'a [CaptionChannel],
+        services: &'a [u8],
+        #[serde(skip_serializing_if = "Option::is_none")]
+        service_data: Option<&'a str>,
+        caption_count: usize,
+    },
+    Caption {
+        segment: Option<&'a str>,
+        #[serde(flatten)]
+        caption: &'a CaptionEvent,
+    },
+    Error {
+        segment: Option<&'a str>,
+        message: &'a str,
+    },
+    PollCycle {
+        segments_processed: usize,
+        captions: usize,
+    },
+}
+
+// Writes progress, segments and captions to stdout in the selected format.
+// The text format is what the tool has always printed; diagnostics go to
+// the log on stderr in every format.
+pub struct Reporter {
+    format: ReportFormat,
+    input: String,
+    list_channels: bool,
+    dump_service: Option<u8>,
+    segments: Vec<SegmentReport>,
+    segments_with_captions: usize,
+    captions: usize,
+}
+
+impl Reporter {
+    pub fn new(format: ReportFormat, input: &str, list_channels: bool, dump_service: Option<u8>) -> Self {
+        Self {
+            format,
+            input: input.to_string(),
+            list_channels,
+            dump_service,
+            segments: Vec::new(),
+            segments_with_captions: 0,
+            captions: 0,
+        }
+    }
+
+    pub fn playlist_found(&self, total_segments: usize) {
+        if self.format == ReportFormat::Text {
+            println!("Found {} segments to process", total_segments);
+        }
+    }
+
+    pub fn segment_started(&self, index: usize, total_segments: usize, uri: &str) {
+        if self.format == ReportFormat::Text {
+            println!("Processing segment {}/{}: {}", index, total_segments, uri);
+        }
+    }
+
+    pub fn progress(&self, processed: usize, total_segments: usize) {
+        if self.format == ReportFormat::Text {
+            println!("Progress: {}/{} segments processed ({:.1}%)",
+                  processed, total_segments,
+                  (processed as f64 / total_segments as f64) * 100.0);
+        }
+    }
+
+    // Segments that failed to download or parse carry their error
+    pub fn segment_processed(&mut self, segment: SegmentReport) -> Result<()> {
+        match self.format {
+            ReportFormat::Text => self.print_segment(&segment),
+            ReportFormat::Json => {}
+            ReportFormat::Ndjson => {
+                for event in segment_events(&segment) {
+                    emit(&event)?;
+                }
+            }
+        }
+        
+        if segment.carries_captions {
+            self.segments_with_captions += 1;
+        }
+        self.captions += segment.captions.len();
+        self.segments.push(segment);
+        Ok(())
+    }
+
+    // Captions still on screen when the playlist ended; they belong to the
+    // last segment
+    pub fn final_captions(&mut self, captions: Vec<CaptionEvent>) -> Result<()> {
+        for caption in &captions {
+            match self.format {
+                ReportFormat::Text => print_caption(caption),
+                ReportFormat::Json => {}
+                ReportFormat::Ndjson => {
+                    let segment = self.segments.last().map(|segment| segment.uri.as_str());
+                    emit(&Event::Caption { segment, caption })?;
+                }
+            }
+        }
+        
+        self.captions += captions.len();
+        if let Some(segment) = self.segments.last_mut() {
+            if !captions.is_empty() && !segment.carries_captions {
+                segment.carries_captions = true;
+                self.segments_with_captions += 1;
+            }
+            segment.captions.extend(captions);
+        }
+        Ok(())
+    }
+
+    // Captions decoded from a UDP stream, as they arrive
+    pub fn stream_captions(&mut self, captions: &[CaptionEvent]) -> Result<()> {
+        for caption in captions {
+            match self.format {
+                ReportFormat::Text => print_caption(caption),
+                ReportFormat::Json => {}
+                ReportFormat::Ndjson => emit(&Event::Caption { segment: None, caption })?,
+            }
+        }
+        self.captions += captions.len();
+        Ok(())
+    }
+
+    pub fn stream_finished(&self) {
+        info!("Stream stopped, {} captions found", self.captions);
+        if self.format == ReportFormat::Text {
+            println!("Stream stopped, {} captions found", self.captions);
+        }
+    }
+
+    // Errors that are not tied to a segment, e.g. a failed playlist reload
+    pub fn error(&self, message: &str) -> Result<()> {
+        if self.format == ReportFormat::Ndjson {
+            emit(&Event::Error { segment: None, message })?;
+        }
+        Ok(())
+    }
+
+    // End of one live playlist poll
+    pub fn poll_cycle(&mut self) -> Result<()> {
+        if self.format == ReportFormat::Ndjson {
+            emit(&Event::PollCycle {
+                segments_processed: self.segments.len(),
+                captions: self.segments.iter().map(|segment| segment.captions.len()).sum(),
+            })?;
+        }
+        // Live playlists run until interrupted; only what is still to come
+        // needs to be kept
+        self.segments.clear();
+        Ok(())
+    }
+
+    pub fn finished(&self, total_segments: usize) -> Result<()> {
+        let report = self.json_report(total_segments);
+        let summary = &report.summary;
+        info!("Summary: {}/{} segments contained captions ({} total captions found)",
+              summary.segments_with_captions, summary.segments, summary.captions);
+        
+        match self.format {
+            ReportFormat::Text => {
+                println!("Completed processing all segments");
+                println!("Summary: {}/{} segments contained captions ({} total captions found)",
+                      summary.segments_with_captions, summary.segments, summary.captions);
+            }
+            ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+            ReportFormat::Ndjson => {}
+        }
+        Ok(())
+    }
+
+    fn json_report(&self, total_segments: usize) -> JsonReport<'_> {
+        JsonReport {
+            input: &self.input,
+            segments: &self.segments,
+            summary: Summary {
+                segments: total_segments,
+                segments_with_captions: self.segments_with_captions,
+                captions: self.captions,
+            },
+        }
+    }
+
+    fn print_segment(&self, segment: &SegmentReport) {
+        if segment.error.is_some() || (!segment.carries_captions && segment.captions.is_empty() && !self.list_channels && segment.service_data.is_none()) {
+            return;
+        }
+        
+        println!("Segment: {}", segment.uri);
+        if self.list_channels {
+            println!("  Channels: {}", join_or_none(&segment.channels));
+            println!("  Services: {}", join_or_none(&segment.services));
+        }
+        if let (Some(service), Some(data)) = (self.dump_service, &segment.service_data) {
+            println!("  Service {} data: {}", service, data);
+        }
+        for caption in &segment.captions {
+            print_caption(caption);
+        }
+    }
+}
+
+// The NDJSON events of a processed segment in the order they are written:
+// the segment, then its captions, or only the error of a failed segment
+fn segment_events(segment: &SegmentReport) -> Vec<Event<'_>> {
+    if let Some(message) = &segment.error {
+        return vec![Event::Error { segment: Some(&segment.uri), message }];
+    }
+    
+    let mut events = vec![Event::SegmentProcessed {
+        uri: &segment.uri,
+        sequence_number: segment.sequence_number,
+        start_time: segment.start_time,
+        duration: segment.duration,
+        channels: &segment.channels,
+        services: &segment.services,
+        service_data: segment.service_data.as_deref(),
+        caption_count: segment.captions.len(),
+    }];
+    events.extend(segment.captions.iter().map(|caption| Event::Caption { segment: Some(&segment.uri), caption }));
+    events
+}
+
+fn emit(event: &Event) -> Result<()> {
+    println!("{}", serde_json::to_string(event)?);
+    Ok(())
+}
+
+fn join_or_none<T: ToString>(items: &[T]) -> String {
+    if items.is_empty() {
+        "none".to_string()
+    } else {
+        items.iter().map(|item| item.to_string()).collect::<Vec<_>>().join(", ")
+    }
+}
+
+fn print_caption(caption: &CaptionEvent) {
+    println!("  Caption {} [{:.3} - {:.3}]: {}", caption.channel, caption.start.seconds, caption.end.seconds, caption.text.replace('\n', " "));
+    debug!("    PTS {} - {}", caption.start.pts, caption.end.pts);
+    for line in &caption.lines {
+        debug!("    Row {} column {}: {:?}", line.row + 1, line.column, line.spans);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hlscaptionfinder::caption_event::CaptionTime;
+    use serde_json::{json, Value};
+
+    fn time(seconds: f64) -> CaptionTime {
+        CaptionTime {
+            pts: (seconds * 90000.0) as u64,
+            seconds,
+        }
+    }
+
+    fn caption(text: &str, start: f64, end: f64) -> CaptionEvent {
+        CaptionEvent {
+            channel: CaptionChannel::Cc1,
+            text: text.to_string(),
+            start: time(start),
+            end: time(end),
+            lines: Vec::new(),
+        }
+    }
+
+    fn segment(uri: &str, sequence_number: u64, captions: Vec<CaptionEvent>) -> SegmentReport {
+        SegmentReport {
+            uri: uri.to_string(),
+            sequence_number,
+            start_time: sequence_number as f64 * 6.0,
+            duration: 6.0,
+            channels: Vec::new(),
+            services: Vec::new(),
+            carries_captions: !captions.is_empty(),
+            service_data: None,
+            error: None,
+            captions,
+        }
+    }
+
+    fn caption_json(text: &str, start: f64, end: f64) -> Value {
+        json!({
+            "channel": "CC1",
+            "text": text,
+            "start": { "pts": (start * 90000.0) as u64, "seconds": start },
+            "end": { "pts": (end * 90000.0) as u64, "seconds": end },
+            "lines": [],
+        })
+    }
+
+    #[test]
+    fn json_report_lists_segments_and_summary() {
+        let mut reporter = Reporter::new(ReportFormat::Json, "index.m3u8", false, None);
+        let mut first = segment("a.ts", 0, vec![caption("HI", 1.0, 2.0)]);
+        first.channels = vec![CaptionChannel::Cc1];
+        reporter.segment_processed(first).unwrap();
+        let mut failed = segment("b.ts", 1, Vec::new());
+        failed.error = Some("timed out".to_string());
+        reporter.segment_processed(failed).unwrap();
+        reporter.segment_processed(segment("c.ts", 2, Vec::new())).unwrap();
+        
+        // Captions still on screen at the end belong to the last segment
+        reporter.final_captions(vec![caption("BYE", 13.0, 14.0)]).unwrap();
+        
+        let report = serde_json::to_value(reporter.json_report(3)).unwrap();
+        assert_eq!(report, json!({
+            "input": "index.m3u8",
+            "segments": [
+                {
+                    "uri": "a.ts",
+                    "sequence_number": 0,
+                    "start_time": 0.0,
+                    "duration": 6.0,
+                    "channels": ["CC1"],
+                    "services": [],
+                    "captions": [caption_json("HI", 1.0, 2.0)],
+                },
+                {
+                    "uri": "b.ts",
+                    "sequence_number": 1,
+                    "start_time": 6.0,
+                    "duration": 6.0,
+                    "channels": [],
+                    "services": [],
+                    "error": "timed out",
+                    "captions": [],
+                },
+                {
+                    "uri": "c.ts",
+                    "sequence_number": 2,
+                    "start_time": 12.0,
+                    "duration": 6.0,
+                    "channels": [],
+                    "services": [],
+                    "captions": [caption_json("BYE", 13.0, 14.0)],
+                },
+            ],
+            "summary": {
+                "segments": 3,
+                "segments_with_captions": 2,
+                "captions": 2,
+            },
+        }));
+    }
+
+    #[test]
+    fn segment_events_come_before_their_captions() {
+        let mut report = segment("a.ts", 0, vec![caption("ONE", 1.0, 2.0), caption("TWO", 2.0, 3.0)]);
+        report.channels = vec![CaptionChannel::Cc1, CaptionChannel::Service(1)];
+        report.services = vec![1];
+        report.service_data = Some("2041".to_string());
+        
+        let events: Vec<Value> = segment_events(&report).iter().map(|event| serde_json::to_value(event).unwrap()).collect();
+        
+        let mut first_caption = caption_json("ONE", 1.0, 2.0);
+        first_caption["event"] = json!("caption");
+        first_caption["segment"] = json!("a.ts");
+        let mut second_caption = caption_json("TWO", 2.0, 3.0);
+        second_caption["event"] = json!("caption");
+        second_caption["segment"] = json!("a.ts");
+        assert_eq!(events, [
+            json!({
+                "event": "segment_processed",
+                "uri": "a.ts",
+                "sequence_number": 0,
+                "start_time": 0.0,
+                "duration": 6.0,
+                "channels": ["CC1", "SERVICE1"],
+                "services": [1],
+                "service_data": "2041",
+                "caption_count": 2,
+            }),
+            first_caption,
+            second_caption,
+        ]);
+    }
+
+    #[test]
+    fn failed_segment_is_a_single_error_event() {
+        let mut report = segment("a.ts", 0, Vec::new());
+        report.error = Some("timed out".to_string());
+        
+        let events: Vec<Value> = segment_events(&report).iter().map(|event| serde_json::to_value(event).unwrap()).collect();
+        
+        assert_eq!(events, [json!({ "event": "error", "segment": "a.ts", "message": "timed out" })]);
+    }
+
+    #[test]
+    fn stream_events_have_no_segment() {
+        let caption = caption("LIVE", 1.0, 2.0);
+        let mut expected = caption_json("LIVE", 1.0, 2.0);
+        expected["event"] = json!("caption");
+        expected["segment"] = Value::Null;
+        
+        assert_eq!(serde_json::to_value(Event::Caption { segment: None, caption: &caption }).unwrap(), expected);
+        assert_eq!(
+            serde_json::to_value(Event::Error { segment: None, message: "reload failed" }).unwrap(),
+            json!({ "event": "error", "segment": null, "message": "reload failed" })
+        );
+        assert_eq!(
+            serde_json::to_value(Event::PollCycle { segments_processed: 2, captions: 3 }).unwrap(),
+            json!({ "event": "poll_cycle", "segments_processed": 2, "captions": 3 })
+        );
+    }
+}
@@ -0,0 +1,310 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use anyhow::{Result, anyhow};
+use log::info;
+use crate::caption_event::{CaptionChannel, CaptionTime};
+use crate::cea708::CaptionData;
+use crate::timecode::{FrameRate, FrameRateDetector};
+
+const SCC_HEADER: &str = "Scenarist_SCC V1.0";
+
+// Writes the raw CEA-608 byte pairs of one caption channel as a Scenarist SCC
+// file. Each line holds the pairs of consecutive frames, starting at the
+// timecode of the first one; padding (0x80 0x80) is left out, so every burst
+// of caption data gets its own line.
+pub struct SccWriter {
+    output: BufWriter<File>,
+    field: u8,
+    data_channel: u8,
+    current_data_channel: u8,
+    in_xds: bool,
+    frame_rate: FrameRateDetector<Vec<CaptionData>>,
+    next_frame: Option<u64>,
+}
+
+impl SccWriter {
+    pub fn create(path: &Path, channel: CaptionChannel) -> Result<Self> {
+        let (field, data_channel) = match channel {
+            CaptionChannel::Cc1 | CaptionChannel::T1 => (1, 1),
+            CaptionChannel::Cc2 | CaptionChannel::T2 => (1, 2),
+            CaptionChannel::Cc3 | CaptionChannel::T3 => (2, 1),
+            CaptionChannel::Cc4 | CaptionChannel::T4 => (2, 2),
+            CaptionChannel::Service(_) => return Err(anyhow!("SCC files only carry CEA-608 channels, not {}", channel)),
+        };
+        
+        let file = File::create(path)
+            .map_err(|e| anyhow!("Unable to create {}: {}", path.display(), e))?;
+        let mut output = BufWriter::new(file);
+        writeln!(output, "{}", SCC_HEADER)?;
+        output.flush()?;
+        
+        info!("Writing {} byte pairs to the SCC file", channel);
+        Ok(Self {
+            output,
+            field,
+            data_channel,
+            current_data_channel: 1,
+            in_xds: false,
+            frame_rate: FrameRateDetector::new(),
+            next_frame: None,
+        })
+    }
+
+    // Takes the cc_data of each frame in presentation order
+    pub fn write_cc_data(&mut self, frames: Vec<(CaptionTime, Vec<CaptionData>)>) -> Result<()> {
+        let frames = self.frame_rate.push(frames);
+        self.write_frames(frames)
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        let frames = self.frame_rate.finish(FrameRate::NTSC);
+        self.write_frames(frames)?;
+        
+        if self.next_frame.take().is_some() {
+            writeln!(self.output)?;
+        }
+        self.output.flush()?;
+        Ok(())
+    }
+
+    fn write_frames(&mut self, frames: Vec<(CaptionTime, Vec<CaptionData>)>) -> Result<()> {
+        for (time, caption_data) in frames {
+            for data in caption_data {
+                if data.cc_valid && data.cc_type + 1 == self.field && self.is_selected(data.cc_data) {
+                    self.write_pair(time, data.cc_data)?;
+                }
+            }
+        }
+        self.output.flush()?;
+        Ok(())
+    }
+
+    // Keeps control codes and characters of the selected data channel, the
+    // way a 608 decoder routes them; XDS on field 2 is left out
+    fn is_selected(&mut self, pair: [u8; 2]) -> bool {
+        let byte1 = pair[0] & 0x7F;
+        let byte2 = pair[1] & 0x7F;
+        
+        if byte1 == 0x00 && byte2 == 0x00 {
+            return false;
+        }
+        if (0x01..=0x0F).contains(&byte1) {
+            self.in_xds = byte1 != 0x0F;
+            return false;
+        }
+        if (0x10..=0x1F).contains(&byte1) {
+            self.in_xds = false;
+            self.current_data_channel = if byte1 & 0x08 != 0 { 2 } else { 1 };
+        } else if self.in_xds || byte1 < 0x20 {
+            return false;
+        }
+        
+        self.current_data_channel == self.data_channel
+    }
+
+    fn write_pair(&mut self, time: CaptionTime, pair: [u8; 2]) -> Result<()> {
+        // SCC words are one per 608 frame, so 50/60 fps video counts in pairs
+        let frame_rate = self.frame_rate.frame_rate().unwrap_or(FrameRate::NTSC).halved();
+        let frame = frame_rate.frame_number(time.seconds);
+        let word = format!("{:02x}{:02x}", with_parity(pair[0]), with_parity(pair[1]));
+        
+        // Pairs continue the current line as long as they fill consecutive
+        // frames; a gap starts a new line at the right timecode
+        match self.next_frame {
+            Some(next_frame) if frame <= next_frame => {
+                write!(self.output, " {}", word)?;
+                self.next_frame = Some(next_frame + 1);
+            }
+            _ => {
+                if self.next_frame.is_some() {
+                    writeln!(self.output)?;
+                }
+                write!(self.output, "\n{}\t{}", frame_rate.timecode(frame), word)?;
+                self.next_frame = Some(frame + 1);
+            }
+        }
+        Ok(())
+    }
+}
+
+// CEA-608 bytes carry odd parity in their top bit
+fn with_parity(byte: u8) -> u8 {
+    let byte = byte & 0x7F;
+    if byte.count_ones().is_multiple_of(2) {
+        byte | 0x80
+    } else {
+        byte
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cea608::Cea608Demuxer;
+    use crate::caption_event::CaptionEvent;
+
+    const FRAME_TICKS: u64 = 3003;
+
+    fn time(frame: u64) -> CaptionTime {
+        CaptionTime {
+            pts: frame * FRAME_TICKS,
+            seconds: frame as f64 * 1001.0 / 30000.0,
+        }
+    }
+
+    // 29.97 fps frames up to the last one given, carrying the (frame, field,
+    // data1, data2) pairs and padding elsewhere
+    fn frames(pairs: &[(u64, u8, u8, u8)]) -> Vec<(CaptionTime, Vec<CaptionData>)> {
+        let last = pairs.iter().map(|pair| pair.0).max().unwrap_or(0);
+        (0..=last)
+            .map(|frame| {
+                let mut caption_data: Vec<_> = pairs
+                    .iter()
+                    .filter(|pair| pair.0 == frame)
+                    .map(|&(_, field, data1, data2)| CaptionData {
+                        cc_valid: true,
+                        cc_type: field - 1,
+                        cc_data: [with_parity(data1), with_parity(data2)],
+                    })
+                    .collect();
+                if caption_data.is_empty() {
+                    caption_data.push(CaptionData { cc_valid: true, cc_type: 0, cc_data: [0x80, 0x80] });
+                }
+                (time(frame), caption_data)
+            })
+            .collect()
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("hlscaptionfinder-{}-{}", std::process::id(), name))
+    }
+
+    fn write(name: &str, channel: CaptionChannel, pairs: &[(u64, u8, u8, u8)]) -> String {
+        let path = temp_path(name);
+        let mut writer = SccWriter::create(&path, channel).unwrap();
+        writer.write_cc_data(frames(pairs)).unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+        
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        contents
+    }
+
+    // Decodes an SCC file of the first minute, where drop-frame timecode
+    // has not skipped any frame numbers yet
+    fn decode(scc: &str, field: u8) -> Vec<CaptionEvent> {
+        let mut demuxer = Cea608Demuxer::new();
+        let mut captions = Vec::new();
+        let mut last_frame = 0;
+        
+        for line in scc.lines().skip(1).filter(|line| !line.is_empty()) {
+            let (timecode, words) = line.split_once('\t').unwrap();
+            let seconds: u64 = timecode[6..8].parse().unwrap();
+            let first_frame = seconds * 30 + timecode[9..11].parse::<u64>().unwrap();
+            for (frame, word) in (first_frame..).zip(words.split(' ')) {
+                let data1 = u8::from_str_radix(&word[..2], 16).unwrap();
+                let data2 = u8::from_str_radix(&word[2..], 16).unwrap();
+                captions.extend(demuxer.decode_pair(field, data1, data2, time(frame)));
+                last_frame = frame;
+            }
+        }
+        
+        captions.extend(demuxer.flush(time(last_frame + 1)));
+        captions
+    }
+
+    // Pop-on "HI" in CC1, interrupted by CC2 data on the same field
+    const POP_ON: [(u64, u8, u8, u8); 12] = [
+        (0, 1, 0x14, 0x20),
+        (1, 1, 0x14, 0x20),
+        (2, 1, b'H', b'I'),
+        (3, 1, 0x1C, 0x20),
+        (4, 1, 0x1C, 0x20),
+        (5, 1, b'X', b'Y'),
+        (6, 1, 0x14, 0x2F),
+        (7, 1, 0x14, 0x2F),
+        (20, 1, 0x14, 0x2C),
+        (21, 1, 0x14, 0x2C),
+        // Field 2 data is not written for a field 1 channel
+        (8, 2, 0x15, 0x20),
+        (9, 2, b'Z', b'Z'),
+    ];
+
+    #[test]
+    fn adds_odd_parity() {
+        assert_eq!(with_parity(0x14), 0x94);
+        assert_eq!(with_parity(0x20), 0x20);
+        assert_eq!(with_parity(b'H'), 0xC8);
+        assert_eq!(with_parity(b'I'), 0x49);
+        assert_eq!(with_parity(0x80), 0x80);
+        assert_eq!(with_parity(0xC8), 0xC8);
+    }
+
+    #[test]
+    fn routes_data_channels_like_a_decoder() {
+        let path = temp_path("routing.scc");
+        let mut writer = SccWriter::create(&path, CaptionChannel::Cc2).unwrap();
+        
+        assert!(!writer.is_selected([0x14, 0x20]));
+        assert!(!writer.is_selected([b'A', b'B']));
+        assert!(writer.is_selected([0x1C, 0x20]));
+        assert!(writer.is_selected([b'A', b'B']));
+        assert!(!writer.is_selected([0x80, 0x80]));
+        
+        // XDS packets interrupt the caption data until their end code
+        assert!(!writer.is_selected([0x01, 0x03]));
+        assert!(!writer.is_selected([b'X', b'D']));
+        assert!(!writer.is_selected([0x0F, 0x1D]));
+        assert!(writer.is_selected([b'A', b'B']));
+        
+        std::fs::remove_file(&path).unwrap();
+        assert!(SccWriter::create(&path, CaptionChannel::Service(1)).is_err());
+    }
+
+    #[test]
+    fn groups_consecutive_frames_on_one_line() {
+        let scc = write("groups.scc", CaptionChannel::Cc1, &POP_ON);
+        
+        assert_eq!(
+            scc,
+            "Scenarist_SCC V1.0\n\n\
+             00:00:00;00\t9420 9420 c849\n\n\
+             00:00:00;06\t942f 942f\n\n\
+             00:00:00;20\t942c 942c\n"
+        );
+    }
+
+    #[test]
+    fn timecodes_drop_frame_numbers() {
+        let scc = write("drop-frame.scc", CaptionChannel::Cc1, &[(1800, 1, 0x14, 0x2C), (17982, 1, 0x14, 0x2C)]);
+        
+        // Frame 1800 is a minute in, where frame numbers 00 and 01 are
+        // skipped; every tenth minute keeps them
+        let timecodes: Vec<_> = scc.lines().filter_map(|line| line.split_once('\t')).map(|(timecode, _)| timecode).collect();
+        assert_eq!(timecodes, ["00:01:00;02", "00:10:00;00"]);
+    }
+
+    #[test]
+    fn written_captions_decode_the_same() {
+        let captions = decode(&write("round-trip-cc1.scc", CaptionChannel::Cc1, &POP_ON), 1);
+        
+        assert_eq!(captions.len(), 1);
+        assert_eq!(captions[0].channel, CaptionChannel::Cc1);
+        assert_eq!(captions[0].text, "HI");
+        assert_eq!(captions[0].start, time(6));
+        assert_eq!(captions[0].end, time(20));
+    }
+
+    #[test]
+    fn field_2_channels_decode_the_same() {
+        let pairs: Vec<_> = POP_ON.iter().filter(|pair| pair.1 == 1).map(|&(frame, _, data1, data2)| (frame, 2, data1, data2)).collect();
+        
+        let captions = decode(&write("round-trip-cc3.scc", CaptionChannel::Cc3, &pairs), 2);
+        
+        assert_eq!(captions.len(), 1);
+        assert_eq!(captions[0].channel, CaptionChannel::Cc3);
+        assert_eq!(captions[0].text, "HI");
+    }
+}
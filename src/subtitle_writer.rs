@@ -0,0 +1,327 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use anyhow::{Result, anyhow};
+use log::{debug, info, warn};
+use crate::caption_event::{CaptionChannel, CaptionColor, CaptionEvent, CaptionLine, CaptionSpan};
+use crate::cea608::{SCREEN_COLUMNS, SCREEN_ROWS};
+
+// CEA-608 captions are positioned inside the title-safe area, which covers
+// the middle 80% of the picture
+pub const SAFE_AREA_MARGIN: f64 = 10.0;
+pub const SAFE_AREA_SIZE: f64 = 80.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubtitleFormat {
+    Srt,
+    Vtt,
+}
+
+// Writes captions as sidecar subtitle cues. Cues are written as soon as a
+// caption ends, so the file stays usable while a live stream is monitored.
+// A sidecar file holds a single track, so only the channel of the first
+// caption written is kept, and captions of other channels are reported once
+// and dropped.
+pub struct SubtitleWriter {
+    format: SubtitleFormat,
+    output: BufWriter<File>,
+    channel: Option<CaptionChannel>,
+    dropped_channels: Vec<CaptionChannel>,
+    cue_count: usize,
+}
+
+impl SubtitleWriter {
+    pub fn create(path: &Path, format: SubtitleFormat) -> Result<Self> {
+        let file = File::create(path)
+            .map_err(|e| anyhow!("Unable to create {}: {}", path.display(), e))?;
+        let mut output = BufWriter::new(file);
+        
+        if format == SubtitleFormat::Vtt {
+            writeln!(output, "WEBVTT")?;
+            writeln!(output)?;
+        }
+        output.flush()?;
+        
+        Ok(Self {
+            format,
+            output,
+            channel: None,
+            dropped_channels: Vec::new(),
+            cue_count: 0,
+        })
+    }
+
+    pub fn write_caption(&mut self, caption: &CaptionEvent) -> Result<()> {
+        let channel = *self.channel.get_or_insert_with(|| {
+            info!("Writing {} captions to the output file", caption.channel);
+            caption.channel
+        });
+        if caption.channel != channel {
+            if !self.dropped_channels.contains(&caption.channel) {
+                warn!("Not writing {} captions: the output file only holds {}", caption.channel, channel);
+                self.dropped_channels.push(caption.channel);
+            }
+            return Ok(());
+        }
+        
+        if caption.end.seconds <= caption.start.seconds {
+            debug!("Skipping zero-length caption at {:.3}: {}", caption.start.seconds, caption.text);
+            return Ok(());
+        }
+        
+        self.cue_count += 1;
+        match self.format {
+            SubtitleFormat::Srt => self.write_srt_cue(caption)?,
+            SubtitleFormat::Vtt => self.write_vtt_cue(caption)?,
+        }
+        self.output.flush()?;
+        Ok(())
+    }
+
+    fn write_srt_cue(&mut self, caption: &CaptionEvent) -> Result<()> {
+        writeln!(self.output, "{}", self.cue_count)?;
+        writeln!(self.output, "{} --> {}", timestamp(caption.start.seconds, ','), timestamp(caption.end.seconds, ','))?;
+        for line in cue_lines(caption) {
+            writeln!(self.output, "{}", line.spans.iter().map(srt_span).collect::<String>())?;
+        }
+        writeln!(self.output)?;
+        Ok(())
+    }
+
+    fn write_vtt_cue(&mut self, caption: &CaptionEvent) -> Result<()> {
+        write!(self.output, "{} --> {}", timestamp(caption.start.seconds, '.'), timestamp(caption.end.seconds, '.'))?;
+        
+        // Position the cue where its first row starts on the caption grid
+        let lines = cue_lines(caption);
+        if let Some(first) = lines.first().filter(|_| !caption.lines.is_empty()) {
+            let line = SAFE_AREA_MARGIN + first.row.min(SCREEN_ROWS - 1) as f64 * SAFE_AREA_SIZE / SCREEN_ROWS as f64;
+            let position = SAFE_AREA_MARGIN + first.column.min(SCREEN_COLUMNS - 1) as f64 * SAFE_AREA_SIZE / SCREEN_COLUMNS as f64;
+            write!(self.output, " line:{:.2}% position:{:.2}% align:start", line, position)?;
+        }
+        writeln!(self.output)?;
+        
+        for line in &lines {
+            writeln!(self.output, "{}", line.spans.iter().map(vtt_span).collect::<String>())?;
+        }
+        writeln!(self.output)?;
+        Ok(())
+    }
+}
+
+// One cue line per caption row, without the padding spaces around the text;
+// captions without layout fall back to their text on the bottom rows
+pub fn cue_lines(caption: &CaptionEvent) -> Vec<CaptionLine> {
+    if caption.lines.is_empty() {
+        let first_row = SCREEN_ROWS.saturating_sub(caption.text.lines().count());
+        return caption.text
+            .lines()
+            .enumerate()
+            .map(|(index, text)| CaptionLine {
+                row: first_row + index,
+                column: 0,
+                spans: vec![CaptionSpan { text: text.trim().to_string(), style: Default::default() }],
+            })
+            .collect();
+    }
+    
+    caption.lines.iter().filter_map(trim_line).collect()
+}
+
+fn trim_line(line: &CaptionLine) -> Option<CaptionLine> {
+    let mut line = line.clone();
+    
+    while let Some(first) = line.spans.first_mut() {
+        let trimmed = first.text.trim_start();
+        line.column += first.text.chars().count() - trimmed.chars().count();
+        first.text = trimmed.to_string();
+        if !first.text.is_empty() {
+            break;
+        }
+        line.spans.remove(0);
+    }
+    
+    while let Some(last) = line.spans.last_mut() {
+        last.text = last.text.trim_end().to_string();
+        if !last.text.is_empty() {
+            break;
+        }
+        line.spans.pop();
+    }
+    
+    if line.spans.is_empty() {
+        None
+    } else {
+        Some(line)
+    }
+}
+
+pub fn timestamp(seconds: f64, separator: char) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        separator,
+        millis % 1000
+    )
+}
+
+fn srt_span(span: &CaptionSpan) -> String {
+    let mut text = span.text.clone();
+    if span.style.underline {
+        text = format!("<u>{}</u>", text);
+    }
+    if span.style.italics {
+        text = format!("<i>{}</i>", text);
+    }
+    text
+}
+
+fn vtt_span(span: &CaptionSpan) -> String {
+    let mut text = span.text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    if span.style.underline {
+        text = format!("<u>{}</u>", text);
+    }
+    if span.style.italics {
+        text = format!("<i>{}</i>", text);
+    }
+    // WebVTT only defines classes for the named palette colors
+    let class = match span.style.color {
+        CaptionColor::White | CaptionColor::Rgb(..) => None,
+        CaptionColor::Green => Some("lime"),
+        CaptionColor::Blue => Some("blue"),
+        CaptionColor::Cyan => Some("cyan"),
+        CaptionColor::Red => Some("red"),
+        CaptionColor::Yellow => Some("yellow"),
+        CaptionColor::Magenta => Some("magenta"),
+        CaptionColor::Black => Some("black"),
+    };
+    match class {
+        Some(class) => format!("<c.{}>{}</c>", class, text),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::caption_event::{CaptionStyle, CaptionTime};
+
+    fn time(seconds: f64) -> CaptionTime {
+        CaptionTime {
+            pts: (seconds * 90000.0) as u64,
+            seconds,
+        }
+    }
+
+    fn caption(channel: CaptionChannel, start: f64, end: f64, lines: Vec<CaptionLine>) -> CaptionEvent {
+        let text = lines.iter().map(|line| line.text().trim().to_string()).collect::<Vec<_>>().join("\n");
+        CaptionEvent {
+            channel,
+            text,
+            start: time(start),
+            end: time(end),
+            lines,
+        }
+    }
+
+    fn line(row: usize, column: usize, spans: &[(&str, CaptionStyle)]) -> CaptionLine {
+        CaptionLine {
+            row,
+            column,
+            spans: spans
+                .iter()
+                .map(|&(text, style)| CaptionSpan { text: text.to_string(), style })
+                .collect(),
+        }
+    }
+
+    fn plain() -> CaptionStyle {
+        CaptionStyle::default()
+    }
+
+    // Writes the captions to a temporary file and returns its contents
+    fn write(name: &str, format: SubtitleFormat, captions: &[CaptionEvent]) -> String {
+        let path = std::env::temp_dir().join(format!("hlscaptionfinder-{}-{}", std::process::id(), name));
+        let mut writer = SubtitleWriter::create(&path, format).unwrap();
+        for caption in captions {
+            writer.write_caption(caption).unwrap();
+        }
+        drop(writer);
+        
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        contents
+    }
+
+    #[test]
+    fn formats_timestamps() {
+        assert_eq!(timestamp(0.0, ','), "00:00:00,000");
+        assert_eq!(timestamp(3723.4567, ','), "01:02:03,457");
+        assert_eq!(timestamp(59.9996, '.'), "00:01:00.000");
+        assert_eq!(timestamp(-1.0, '.'), "00:00:00.000");
+    }
+
+    #[test]
+    fn writes_numbered_srt_cues() {
+        let italics = CaptionStyle { italics: true, ..plain() };
+        let underline = CaptionStyle { underline: true, ..plain() };
+        let captions = [
+            caption(CaptionChannel::Cc1, 1.0, 2.5, vec![line(14, 0, &[("  HELLO ", plain())])]),
+            // Zero-length captions are skipped without using a number
+            caption(CaptionChannel::Cc1, 3.0, 3.0, vec![line(14, 0, &[("GONE", plain())])]),
+            caption(CaptionChannel::Cc1, 4.0, 5.0, vec![
+                line(13, 0, &[("A ", italics), ("<B>", underline)]),
+                line(14, 0, &[("C & D", plain())]),
+            ]),
+        ];
+        
+        let srt = write("cues.srt", SubtitleFormat::Srt, &captions);
+        
+        assert_eq!(
+            srt,
+            "1\n00:00:01,000 --> 00:00:02,500\nHELLO\n\n\
+             2\n00:00:04,000 --> 00:00:05,000\n<i>A </i><u><B></u>\nC & D\n\n"
+        );
+    }
+
+    #[test]
+    fn writes_positioned_vtt_cues() {
+        let red = CaptionStyle { color: CaptionColor::Red, ..plain() };
+        let captions = [
+            caption(CaptionChannel::Cc1, 1.0, 2.0, vec![line(14, 4, &[("    ", plain()), ("<A & B>", red)])]),
+            // Captions without layout sit on the bottom rows with the
+            // default cue position
+            CaptionEvent {
+                channel: CaptionChannel::Cc1,
+                text: "NO\nLAYOUT".to_string(),
+                start: time(3.0),
+                end: time(4.0),
+                lines: Vec::new(),
+            },
+        ];
+        
+        let vtt = write("cues.vtt", SubtitleFormat::Vtt, &captions);
+        
+        assert_eq!(
+            vtt,
+            "WEBVTT\n\n\
+             00:00:01.000 --> 00:00:02.000 line:84.67% position:30.00% align:start\n<c.red>&lt;A &amp; B&gt;</c>\n\n\
+             00:00:03.000 --> 00:00:04.000\nNO\nLAYOUT\n\n"
+        );
+    }
+
+    #[test]
+    fn keeps_the_first_channel_only() {
+        let captions = [
+            caption(CaptionChannel::Service(1), 1.0, 2.0, vec![line(0, 0, &[("FIRST", plain())])]),
+            caption(CaptionChannel::Cc1, 1.0, 2.0, vec![line(14, 0, &[("OTHER", plain())])]),
+            caption(CaptionChannel::Service(1), 3.0, 4.0, vec![line(0, 0, &[("SECOND", plain())])]),
+        ];
+        
+        let srt = write("channels.srt", SubtitleFormat::Srt, &captions);
+        
+        assert_eq!(srt, "1\n00:00:01,000 --> 00:00:02,000\nFIRST\n\n2\n00:00:03,000 --> 00:00:04,000\nSECOND\n\n");
+    }
+}
@@ -0,0 +1,204 @@
+use log::warn;
+use crate::caption_event::CaptionTime;
+
+const PTS_CLOCK_RATE: f64 = 90000.0;
+
+// Standard video frame rates as (nominal frames per second, 1000/1001 pull-down)
+const FRAME_RATES: [(u32, bool); 8] = [
+    (24, true),
+    (24, false),
+    (25, false),
+    (30, true),
+    (30, false),
+    (50, false),
+    (60, true),
+    (60, false),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameRate {
+    pub nominal: u32,
+    pub fractional: bool,
+}
+
+impl FrameRate {
+    pub const NTSC: FrameRate = FrameRate { nominal: 30, fractional: true };
+    
+    // Picks the standard frame rate closest to a frame duration in 90 kHz ticks
+    pub fn from_frame_duration(ticks: u64) -> Self {
+        let fps = PTS_CLOCK_RATE / ticks.max(1) as f64;
+        FRAME_RATES
+            .iter()
+            .map(|&(nominal, fractional)| FrameRate { nominal, fractional })
+            .min_by(|a, b| (a.fps() - fps).abs().total_cmp(&(b.fps() - fps).abs()))
+            .unwrap_or(Self::NTSC)
+    }
+
+    pub fn fps(&self) -> f64 {
+        if self.fractional {
+            self.nominal as f64 * 1000.0 / 1001.0
+        } else {
+            self.nominal as f64
+        }
+    }
+
+    // 29.97 and 59.94 use drop-frame timecode to stay in step with the clock
+    pub fn drop_frame(&self) -> bool {
+        self.fractional && self.nominal.is_multiple_of(30)
+    }
+
+    // The rate with its frames paired up, e.g. 59.94 -> 29.97
+    pub fn halved(&self) -> Self {
+        if self.nominal > 30 {
+            FrameRate { nominal: self.nominal / 2, fractional: self.fractional }
+        } else {
+            *self
+        }
+    }
+
+    pub fn frame_number(&self, seconds: f64) -> u64 {
+        (seconds.max(0.0) * self.fps()).round() as u64
+    }
+
+    // SMPTE timecode of a frame number, HH:MM:SS;FF for drop-frame and
+    // HH:MM:SS:FF otherwise
+    pub fn timecode(&self, frame: u64) -> String {
+        let nominal = self.nominal as u64;
+        let mut frame = frame;
+        
+        // Drop-frame timecode skips the first frame numbers of every minute
+        // except each tenth minute; the frames themselves are all there
+        if self.drop_frame() {
+            let dropped = nominal / 15;
+            let frames_per_minute = nominal * 60 - dropped;
+            let frames_per_ten_minutes = frames_per_minute * 10 + dropped;
+            let ten_minutes = frame / frames_per_ten_minutes;
+            let remainder = frame % frames_per_ten_minutes;
+            frame += dropped * 9 * ten_minutes;
+            if remainder > dropped {
+                frame += dropped * ((remainder - dropped) / frames_per_minute);
+            }
+        }
+        
+        format!(
+            "{:02}:{:02}:{:02}{}{:02}",
+            frame / (nominal * 3600) % 24,
+            frame / (nominal * 60) % 60,
+            frame / nominal % 60,
+            if self.drop_frame() { ';' } else { ':' },
+            frame % nominal
+        )
+    }
+}
+
+// Holds frames back until the frame rate can be told from the first two
+// distinct PTS, which needs frames in presentation order
+pub struct FrameRateDetector<T> {
+    frame_rate: Option<FrameRate>,
+    pending: Vec<(CaptionTime, T)>,
+}
+
+impl<T> FrameRateDetector<T> {
+    pub fn new() -> Self {
+        Self {
+            frame_rate: None,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn frame_rate(&self) -> Option<FrameRate> {
+        self.frame_rate
+    }
+
+    // Returns the frames that can be written, all of them once the rate is known
+    pub fn push(&mut self, frames: Vec<(CaptionTime, T)>) -> Vec<(CaptionTime, T)> {
+        if self.frame_rate.is_some() {
+            return frames;
+        }
+        
+        self.pending.extend(frames);
+        self.frame_rate = self.pending
+            .windows(2)
+            .map(|pair| pair[1].0.pts.saturating_sub(pair[0].0.pts))
+            .find(|&ticks| ticks > 0)
+            .map(FrameRate::from_frame_duration);
+        
+        if self.frame_rate.is_some() {
+            std::mem::take(&mut self.pending)
+        } else {
+            Vec::new()
+        }
+    }
+
+    // Releases frames still pending at the end, assuming the given rate
+    pub fn finish(&mut self, fallback: FrameRate) -> Vec<(CaptionTime, T)> {
+        if self.frame_rate.is_none() && !self.pending.is_empty() {
+            warn!("Unable to detect the frame rate, assuming {:.3} fps", fallback.fps());
+            self.frame_rate = Some(fallback);
+        }
+        std::mem::take(&mut self.pending)
+    }
+}
+
+impl<T> Default for FrameRateDetector<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NTSC_60: FrameRate = FrameRate { nominal: 60, fractional: true };
+    const PAL: FrameRate = FrameRate { nominal: 25, fractional: false };
+
+    #[test]
+    fn drop_frame_skips_frame_numbers_at_minute_boundaries() {
+        let ntsc = FrameRate::NTSC;
+        assert_eq!(ntsc.timecode(0), "00:00:00;00");
+        assert_eq!(ntsc.timecode(1799), "00:00:59;29");
+        assert_eq!(ntsc.timecode(1800), "00:01:00;02");
+        assert_eq!(ntsc.timecode(3597), "00:01:59;29");
+        assert_eq!(ntsc.timecode(3598), "00:02:00;02");
+        // Every tenth minute keeps its first frame numbers
+        assert_eq!(ntsc.timecode(17981), "00:09:59;29");
+        assert_eq!(ntsc.timecode(17982), "00:10:00;00");
+        assert_eq!(ntsc.timecode(17982 + 1800), "00:11:00;02");
+        assert_eq!(ntsc.timecode(107892), "01:00:00;00");
+    }
+
+    #[test]
+    fn drop_frame_at_59_94_skips_four_frame_numbers() {
+        assert_eq!(NTSC_60.timecode(3599), "00:00:59;59");
+        assert_eq!(NTSC_60.timecode(3600), "00:01:00;04");
+        assert_eq!(NTSC_60.timecode(35964), "00:10:00;00");
+    }
+
+    #[test]
+    fn non_drop_frame_counts_every_frame_number() {
+        assert!(!PAL.drop_frame());
+        assert_eq!(PAL.timecode(1500), "00:01:00:00");
+        assert_eq!(PAL.timecode(90000 - 1), "00:59:59:24");
+    }
+
+    #[test]
+    fn detects_standard_frame_rates() {
+        assert_eq!(FrameRate::from_frame_duration(3003), FrameRate::NTSC);
+        assert_eq!(FrameRate::from_frame_duration(3600), PAL);
+        assert_eq!(FrameRate::from_frame_duration(1501), NTSC_60);
+        assert_eq!(NTSC_60.halved(), FrameRate::NTSC);
+    }
+
+    #[test]
+    fn detector_holds_frames_until_rate_is_known() {
+        let time = |pts| CaptionTime { pts, seconds: 0.0 };
+        let mut detector = FrameRateDetector::new();
+        assert!(detector.push(vec![(time(0), 'a')]).is_empty());
+        
+        let frames = detector.push(vec![(time(3600), 'b')]);
+        assert_eq!(frames.iter().map(|(_, frame)| *frame).collect::<String>(), "ab");
+        assert_eq!(detector.frame_rate(), Some(PAL));
+        assert_eq!(detector.push(vec![(time(7200), 'c')]).len(), 1);
+    }
+}
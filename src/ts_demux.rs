@@ -0,0 +1,303 @@
+use log::debug;
+use crate::nal::VideoCodec;
+
+pub const TS_PACKET_SIZE: usize = 188;
+pub const TS_SYNC_BYTE: u8 = 0x47;
+const PAT_PID: u16 = 0x0000;
+
+// The payload of one transport stream packet of the video stream
+pub struct TsPayload<'a> {
+    pub pid: u16,
+    // payload_unit_start_indicator: a PES packet starts in this payload
+    pub unit_start: bool,
+    // discontinuity_indicator in the adaptation field
+    pub discontinuity: bool,
+    // Empty for packets that only carry an adaptation field
+    pub data: &'a [u8],
+}
+
+// Finds the video stream through the PAT and PMT, and hands out the
+// payloads of its packets
+#[derive(Default)]
+pub struct TsDemuxer {
+    pmt_pid: Option<u16>,
+    pmt_version: Option<u8>,
+    video_pid: Option<u16>,
+    codec: Option<VideoCodec>,
+}
+
+impl TsDemuxer {
+    pub fn new() -> Self {
+        Self {
+            pmt_pid: None,
+            pmt_version: None,
+            video_pid: None,
+            codec: None,
+        }
+    }
+
+    // Codec of the video stream, once the PMT has been seen
+    pub fn codec(&self) -> Option<VideoCodec> {
+        self.codec
+    }
+
+    pub fn push_packet<'a>(&mut self, packet: &'a [u8]) -> Option<TsPayload<'a>> {
+        // Verify sync byte
+        if packet.len() < TS_PACKET_SIZE || packet[0] != TS_SYNC_BYTE {
+            return None;
+        }
+        
+        let pid = ((packet[1] as u16 & 0x1F) << 8) | packet[2] as u16;
+        
+        // The PAT and PMT keep being parsed, so that a new PMT version (such
+        // as a change of video stream on a long running input) takes effect
+        if pid == PAT_PID {
+            self.parse_pat_packet(packet);
+            return None;
+        }
+        if self.pmt_pid == Some(pid) {
+            self.parse_pmt_packet(packet);
+            return None;
+        }
+        
+        // Only process packets for the video stream's PID
+        if self.video_pid != Some(pid) {
+            return None;
+        }
+        
+        let adaptation_field = (packet[3] & 0x20) != 0;
+        let payload_present = (packet[3] & 0x10) != 0;
+        let discontinuity = adaptation_field && packet[4] > 0 && (packet[5] & 0x80) != 0;
+        
+        let data = if payload_present {
+            packet.get(payload_offset(packet)..).unwrap_or_default()
+        } else {
+            &[]
+        };
+        
+        Some(TsPayload {
+            pid,
+            unit_start: (packet[1] & 0x40) != 0,
+            discontinuity,
+            data,
+        })
+    }
+
+    // Takes the PMT PID of the first program. Program number 0 points at the
+    // network information table instead of a PMT and is skipped.
+    fn parse_pat_packet(&mut self, packet: &[u8]) {
+        let Some(section) = psi_section(packet) else {
+            return;
+        };
+        
+        if section.len() < 8 {
+            return;
+        }
+        
+        let section_length = ((section[1] as usize & 0x0F) << 8) | section[2] as usize;
+        // Program entries run from after the header up to the CRC
+        let end = (3 + section_length).saturating_sub(4).min(section.len());
+        
+        let pmt_pid = section.get(8..end).unwrap_or_default()
+            .chunks_exact(4)
+            .find(|entry| u16::from_be_bytes([entry[0], entry[1]]) != 0)
+            .map(|entry| ((entry[2] as u16 & 0x1F) << 8) | entry[3] as u16);
+        
+        if pmt_pid.is_some() && pmt_pid != self.pmt_pid {
+            debug!("PMT on PID {:?}", pmt_pid);
+            self.pmt_pid = pmt_pid;
+            self.pmt_version = None;
+        }
+    }
+
+    // Takes the first video stream of the PMT, each time its version changes
+    fn parse_pmt_packet(&mut self, packet: &[u8]) {
+        let Some(section) = psi_section(packet) else {
+            return;
+        };
+        
+        if section.len() < 12 {
+            return;
+        }
+        
+        let section_length = ((section[1] as u16 & 0x0F) << 8) | section[2] as u16;
+        let version = (section[5] >> 1) & 0x1F;
+        let current = (section[5] & 0x01) != 0;
+        let program_info_length = ((section[10] as u16 & 0x0F) << 8) | section[11] as u16;
+        let mut descriptor_loop_length = section_length as i32 - (9 + program_info_length as i32 + 4);
+        
+        if !current || self.pmt_version == Some(version) {
+            return;
+        }
+        self.pmt_version = Some(version);
+        
+        let mut i = 12 + program_info_length as usize;
+        let mut video = None;
+        
+        while descriptor_loop_length >= 5 && i + 5 <= section.len() {
+            let stream_type = section[i];
+            let elementary_pid = ((section[i + 1] as u16 & 0x1F) << 8) | section[i + 2] as u16;
+            let esinfo_length = ((section[i + 3] as u16 & 0x0F) << 8) | section[i + 4] as u16;
+            
+            // Find video stream
+            if let Some(codec) = VideoCodec::from_stream_type(stream_type) {
+                video = Some((elementary_pid, codec));
+                break;
+            }
+            
+            i += 5 + esinfo_length as usize;
+            descriptor_loop_length -= 5 + esinfo_length as i32;
+        }
+        
+        debug!("PMT version {}: video stream {:?}", version, video);
+        self.video_pid = video.map(|(pid, _)| pid);
+        self.codec = video.map(|(_, codec)| codec);
+    }
+}
+
+// Offset of the payload, after the header and any adaptation field
+fn payload_offset(packet: &[u8]) -> usize {
+    if (packet[3] & 0x20) != 0 {
+        5 + packet[4] as usize
+    } else {
+        4
+    }
+}
+
+// The PSI section starting in a packet, after its pointer field
+fn psi_section(packet: &[u8]) -> Option<&[u8]> {
+    let payload_present = (packet[3] & 0x10) != 0;
+    if !payload_present {
+        return None;
+    }
+    
+    let payload = packet.get(payload_offset(packet)..)?;
+    let pointer_field = *payload.first()? as usize;
+    payload.get(1 + pointer_field..)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    
+    // One packet carrying a whole PSI section. The section length is filled
+    // in; the CRC is not checked and left zero.
+    fn psi_packet(pid: u16, mut section: Vec<u8>) -> Vec<u8> {
+        let section_length = section.len() - 3 + 4;
+        section[1] = 0xB0 | (section_length >> 8) as u8;
+        section[2] = section_length as u8;
+        section.extend([0; 4]);
+        
+        let mut packet = vec![TS_SYNC_BYTE, 0x40 | (pid >> 8) as u8, pid as u8, 0x10, 0x00];
+        packet.extend(section);
+        packet.resize(TS_PACKET_SIZE, 0xFF);
+        packet
+    }
+
+    // A PAT listing (program_number, PMT PID) entries
+    pub(crate) fn pat_packet(programs: &[(u16, u16)]) -> Vec<u8> {
+        let mut section = vec![0x00, 0, 0, 0x00, 0x01, 0xC1, 0x00, 0x00];
+        for &(program_number, pmt_pid) in programs {
+            section.extend(program_number.to_be_bytes());
+            section.extend((0xE000 | pmt_pid).to_be_bytes());
+        }
+        psi_packet(PAT_PID, section)
+    }
+
+    // A PMT listing (stream_type, elementary PID) entries
+    pub(crate) fn pmt_packet(pmt_pid: u16, version: u8, streams: &[(u8, u16)]) -> Vec<u8> {
+        let mut section = vec![0x02, 0, 0, 0x00, 0x01, 0xC1 | (version << 1), 0x00, 0x00, 0xE1, 0x00, 0xF0, 0x00];
+        for &(stream_type, pid) in streams {
+            section.push(stream_type);
+            section.extend((0xE000 | pid).to_be_bytes());
+            section.extend([0xF0, 0x00]);
+        }
+        psi_packet(pmt_pid, section)
+    }
+
+    // Packets carrying data on a PID, the first one with the payload unit
+    // start flag and the last one padded with adaptation field stuffing
+    pub(crate) fn payload_packets(pid: u16, data: &[u8]) -> Vec<u8> {
+        let mut packets = Vec::new();
+        for (index, chunk) in data.chunks(TS_PACKET_SIZE - 4).enumerate() {
+            let unit_start = if index == 0 { 0x40 } else { 0x00 };
+            let counter = index as u8 & 0x0F;
+            if chunk.len() == TS_PACKET_SIZE - 4 {
+                packets.extend([TS_SYNC_BYTE, unit_start | (pid >> 8) as u8, pid as u8, 0x10 | counter]);
+            } else {
+                let stuffing = TS_PACKET_SIZE - 5 - chunk.len();
+                packets.extend([TS_SYNC_BYTE, unit_start | (pid >> 8) as u8, pid as u8, 0x30 | counter, stuffing as u8]);
+                if stuffing > 0 {
+                    packets.push(0x00);
+                    packets.resize(packets.len() + stuffing - 1, 0xFF);
+                }
+            }
+            packets.extend_from_slice(chunk);
+        }
+        packets
+    }
+
+    fn payload<'a>(demuxer: &mut TsDemuxer, packet: &'a [u8]) -> Option<(u16, bool, &'a [u8])> {
+        demuxer.push_packet(packet).map(|payload| (payload.pid, payload.unit_start, payload.data))
+    }
+
+    #[test]
+    fn finds_video_stream_through_pat_and_pmt() {
+        let mut demuxer = TsDemuxer::new();
+        let video = payload_packets(0x100, b"video");
+        
+        // Nothing is known before the PMT
+        assert!(demuxer.push_packet(&video).is_none());
+        
+        // The NIT entry (program 0) comes first and is skipped
+        assert!(demuxer.push_packet(&pat_packet(&[(0, 0x10), (1, 0x1000)])).is_none());
+        assert!(demuxer.push_packet(&pmt_packet(0x1000, 0, &[(0x0F, 0x101), (0x1B, 0x100)])).is_none());
+        
+        assert_eq!(demuxer.codec(), Some(VideoCodec::H264));
+        assert_eq!(payload(&mut demuxer, &video), Some((0x100, true, &b"video"[..])));
+        assert!(demuxer.push_packet(&payload_packets(0x101, b"audio")).is_none());
+    }
+
+    #[test]
+    fn pmt_on_nit_pid_is_not_used() {
+        let mut demuxer = TsDemuxer::new();
+        demuxer.push_packet(&pat_packet(&[(0, 0x10), (1, 0x1000)]));
+        demuxer.push_packet(&pmt_packet(0x10, 0, &[(0x1B, 0x100)]));
+        
+        assert_eq!(demuxer.codec(), None);
+        assert!(demuxer.push_packet(&payload_packets(0x100, b"video")).is_none());
+    }
+
+    #[test]
+    fn new_pmt_version_switches_video_stream() {
+        let mut demuxer = TsDemuxer::new();
+        demuxer.push_packet(&pat_packet(&[(1, 0x1000)]));
+        demuxer.push_packet(&pmt_packet(0x1000, 0, &[(0x1B, 0x100)]));
+        assert!(payload(&mut demuxer, &payload_packets(0x100, b"h264")).is_some());
+        
+        demuxer.push_packet(&pmt_packet(0x1000, 1, &[(0x24, 0x200)]));
+        
+        assert_eq!(demuxer.codec(), Some(VideoCodec::Hevc));
+        assert!(demuxer.push_packet(&payload_packets(0x100, b"h264")).is_none());
+        assert_eq!(payload(&mut demuxer, &payload_packets(0x200, b"hevc")), Some((0x200, true, &b"hevc"[..])));
+        
+        // A repeated PMT of the same version changes nothing
+        demuxer.push_packet(&pmt_packet(0x1000, 1, &[(0x1B, 0x100)]));
+        assert_eq!(demuxer.codec(), Some(VideoCodec::Hevc));
+    }
+
+    #[test]
+    fn reads_discontinuity_indicator() {
+        let mut demuxer = TsDemuxer::new();
+        demuxer.push_packet(&pat_packet(&[(1, 0x1000)]));
+        demuxer.push_packet(&pmt_packet(0x1000, 0, &[(0x1B, 0x100)]));
+        
+        let mut packet = payload_packets(0x100, b"video");
+        assert!(!demuxer.push_packet(&packet).unwrap().discontinuity);
+        
+        packet[5] = 0x80;
+        let payload = demuxer.push_packet(&packet).unwrap();
+        assert!(payload.discontinuity);
+        assert_eq!(payload.data, b"video");
+    }
+}
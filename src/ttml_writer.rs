@@ -0,0 +1,265 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use log::{debug, info};
+use crate::caption_event::{CaptionChannel, CaptionColor, CaptionEvent, CaptionLine, CaptionSpan};
+use crate::cea608::{SCREEN_COLUMNS, SCREEN_ROWS};
+use crate::subtitle_writer::{cue_lines, timestamp, SAFE_AREA_MARGIN, SAFE_AREA_SIZE};
+
+const IMSC1_TEXT_PROFILE: &str = "http://www.w3.org/ns/ttml/profile/imsc1/text";
+// IMSC1 shows at most four regions at once, so the 15 caption rows are
+// split into four bands of up to four rows
+const BAND_ROWS: usize = 4;
+
+// Writes captions as an IMSC1 text profile TTML document. The rows of the
+// 15x32 caption grid, which covers the title-safe area the same way the
+// WebVTT cue settings do, are grouped into row bands, and each band is a
+// region across the safe area. The rows of a caption that fall into one band
+// share a paragraph, so CEA-708 windows side by side do not overlap; rows
+// keep their place within the band and are centered. The document is only
+// complete once all captions are known, so it is written by finish.
+pub struct TtmlWriter {
+    path: PathBuf,
+    channel: Option<CaptionChannel>,
+    captions: Vec<CaptionEvent>,
+}
+
+impl TtmlWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        // Fail early rather than after the whole playlist has been processed
+        File::create(path).map_err(|e| anyhow!("Unable to create {}: {}", path.display(), e))?;
+        
+        Ok(Self {
+            path: path.to_path_buf(),
+            channel: None,
+            captions: Vec::new(),
+        })
+    }
+
+    pub fn write_caption(&mut self, caption: &CaptionEvent) -> Result<()> {
+        // A document holds a single track, like the sidecar subtitle formats
+        let channel = *self.channel.get_or_insert_with(|| {
+            info!("Writing {} captions to the TTML document", caption.channel);
+            caption.channel
+        });
+        if caption.channel != channel {
+            return Ok(());
+        }
+        
+        if caption.end.seconds <= caption.start.seconds {
+            debug!("Skipping zero-length caption at {:.3}: {}", caption.start.seconds, caption.text);
+            return Ok(());
+        }
+        
+        self.captions.push(caption.clone());
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        let file = File::create(&self.path)
+            .map_err(|e| anyhow!("Unable to create {}: {}", self.path.display(), e))?;
+        let mut output = BufWriter::new(file);
+        
+        let bands: BTreeSet<usize> = self.captions
+            .iter()
+            .flat_map(|caption| cue_lines(caption).into_iter().map(|line| band(line.row)))
+            .collect();
+        
+        writeln!(output, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            output,
+            r#"<tt xmlns="http://www.w3.org/ns/ttml" xmlns:ttp="http://www.w3.org/ns/ttml#parameter" xmlns:tts="http://www.w3.org/ns/ttml#styling" xml:lang="" ttp:profile="{}" ttp:timeBase="media" ttp:cellResolution="{} {}">"#,
+            IMSC1_TEXT_PROFILE, SCREEN_COLUMNS, SCREEN_ROWS
+        )?;
+        writeln!(output, "  <head>")?;
+        writeln!(output, "    <styling>")?;
+        // Caption rows take 80% of a cell, the share of the title-safe area
+        writeln!(
+            output,
+            r#"      <style xml:id="caption" tts:fontFamily="monospaceSansSerif" tts:fontSize="80%" tts:lineHeight="125%" tts:color="white" tts:backgroundColor="black"/>"#
+        )?;
+        writeln!(output, "    </styling>")?;
+        writeln!(output, "    <layout>")?;
+        for &band in &bands {
+            let first_row = band * BAND_ROWS;
+            let rows = BAND_ROWS.min(SCREEN_ROWS - first_row);
+            writeln!(
+                output,
+                r#"      <region xml:id="{}" tts:origin="{:.3}% {:.3}%" tts:extent="{:.3}% {:.3}%" tts:displayAlign="before" tts:textAlign="center" tts:showBackground="whenActive"/>"#,
+                region_id(band),
+                SAFE_AREA_MARGIN,
+                SAFE_AREA_MARGIN + first_row as f64 * SAFE_AREA_SIZE / SCREEN_ROWS as f64,
+                SAFE_AREA_SIZE,
+                rows as f64 * SAFE_AREA_SIZE / SCREEN_ROWS as f64
+            )?;
+        }
+        writeln!(output, "    </layout>")?;
+        writeln!(output, "  </head>")?;
+        writeln!(output, "  <body style=\"caption\">")?;
+        writeln!(output, "    <div>")?;
+        
+        for caption in &self.captions {
+            let begin = timestamp(caption.start.seconds, '.');
+            let end = timestamp(caption.end.seconds, '.');
+            let mut band_lines: BTreeMap<usize, Vec<CaptionLine>> = BTreeMap::new();
+            for line in cue_lines(caption) {
+                band_lines.entry(band(line.row)).or_default().push(line);
+            }
+            for (band, lines) in band_lines {
+                writeln!(output, r#"      <p begin="{}" end="{}" region="{}">{}</p>"#, begin, end, region_id(band), band_text(band, &lines))?;
+            }
+        }
+        
+        writeln!(output, "    </div>")?;
+        writeln!(output, "  </body>")?;
+        writeln!(output, "</tt>")?;
+        output.flush()?;
+        
+        info!("Wrote {} captions to {}", self.captions.len(), self.path.display());
+        Ok(())
+    }
+}
+
+fn band(row: usize) -> usize {
+    row.min(SCREEN_ROWS - 1) / BAND_ROWS
+}
+
+fn region_id(band: usize) -> String {
+    format!("band{}", band + 1)
+}
+
+// The rows of a band from its top down to the last one with text, with
+// empty rows kept as line breaks. Lines on the same row, from windows side
+// by side, are joined left to right.
+fn band_text(band: usize, lines: &[CaptionLine]) -> String {
+    let first_row = band * BAND_ROWS;
+    let last_row = lines.iter().map(|line| line.row.min(SCREEN_ROWS - 1)).max().unwrap_or(first_row);
+    
+    (first_row..=last_row)
+        .map(|row| {
+            let mut row_lines: Vec<_> = lines.iter().filter(|line| line.row.min(SCREEN_ROWS - 1) == row).collect();
+            row_lines.sort_by_key(|line| line.column);
+            row_lines
+                .iter()
+                .map(|line| line.spans.iter().map(ttml_span).collect::<String>())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>()
+        .join("<br/>")
+}
+
+fn ttml_span(span: &CaptionSpan) -> String {
+    let text = span.text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    let mut attributes = String::new();
+    
+    if span.style.italics {
+        attributes.push_str(r#" tts:fontStyle="italic""#);
+    }
+    if span.style.underline {
+        attributes.push_str(r#" tts:textDecoration="underline""#);
+    }
+    let color = match span.style.color {
+        CaptionColor::White => None,
+        CaptionColor::Green => Some("lime".to_string()),
+        CaptionColor::Blue => Some("blue".to_string()),
+        CaptionColor::Cyan => Some("cyan".to_string()),
+        CaptionColor::Red => Some("red".to_string()),
+        CaptionColor::Yellow => Some("yellow".to_string()),
+        CaptionColor::Magenta => Some("magenta".to_string()),
+        CaptionColor::Black => Some("black".to_string()),
+        CaptionColor::Rgb(r, g, b) => Some(format!("#{:02x}{:02x}{:02x}", r, g, b)),
+    };
+    if let Some(color) = color {
+        attributes.push_str(&format!(r#" tts:color="{}""#, color));
+    }
+    
+    if attributes.is_empty() {
+        text
+    } else {
+        format!("<span{}>{}</span>", attributes, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::caption_event::{CaptionStyle, CaptionTime};
+
+    fn time(seconds: f64) -> CaptionTime {
+        CaptionTime {
+            pts: (seconds * 90000.0) as u64,
+            seconds,
+        }
+    }
+
+    fn caption(start: f64, end: f64, lines: &[(usize, usize, &str)]) -> CaptionEvent {
+        let lines: Vec<_> = lines
+            .iter()
+            .map(|&(row, column, text)| CaptionLine {
+                row,
+                column,
+                spans: vec![CaptionSpan { text: text.to_string(), style: CaptionStyle::default() }],
+            })
+            .collect();
+        CaptionEvent {
+            channel: CaptionChannel::Service(1),
+            text: lines.iter().map(CaptionLine::text).collect::<Vec<_>>().join("\n"),
+            start: time(start),
+            end: time(end),
+            lines,
+        }
+    }
+
+    fn write(name: &str, captions: &[CaptionEvent]) -> String {
+        let path = std::env::temp_dir().join(format!("hlscaptionfinder-{}-{}", std::process::id(), name));
+        let mut writer = TtmlWriter::create(&path).unwrap();
+        for caption in captions {
+            writer.write_caption(caption).unwrap();
+        }
+        writer.finish().unwrap();
+        
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        contents
+    }
+
+    fn matching_lines<'a>(document: &'a str, prefix: &str) -> Vec<&'a str> {
+        document.lines().map(str::trim).filter(|line| line.starts_with(prefix)).collect()
+    }
+
+    #[test]
+    fn rows_share_at_most_four_band_regions() {
+        let document = write("bands.ttml", &[
+            caption(1.0, 2.0, &[(0, 4, "TOP"), (13, 2, "ONE"), (14, 2, "TWO")]),
+            caption(2.0, 3.0, &[(5, 0, "MIDDLE"), (10, 8, "LOWER")]),
+        ]);
+        
+        assert_eq!(matching_lines(&document, "<region"), [
+            r#"<region xml:id="band1" tts:origin="10.000% 10.000%" tts:extent="80.000% 21.333%" tts:displayAlign="before" tts:textAlign="center" tts:showBackground="whenActive"/>"#,
+            r#"<region xml:id="band2" tts:origin="10.000% 31.333%" tts:extent="80.000% 21.333%" tts:displayAlign="before" tts:textAlign="center" tts:showBackground="whenActive"/>"#,
+            r#"<region xml:id="band3" tts:origin="10.000% 52.667%" tts:extent="80.000% 21.333%" tts:displayAlign="before" tts:textAlign="center" tts:showBackground="whenActive"/>"#,
+            r#"<region xml:id="band4" tts:origin="10.000% 74.000%" tts:extent="80.000% 16.000%" tts:displayAlign="before" tts:textAlign="center" tts:showBackground="whenActive"/>"#,
+        ]);
+        
+        // Rows keep their place within the band
+        assert_eq!(matching_lines(&document, "<p"), [
+            r#"<p begin="00:00:01.000" end="00:00:02.000" region="band1">TOP</p>"#,
+            r#"<p begin="00:00:01.000" end="00:00:02.000" region="band4"><br/>ONE<br/>TWO</p>"#,
+            r#"<p begin="00:00:02.000" end="00:00:03.000" region="band2"><br/>MIDDLE</p>"#,
+            r#"<p begin="00:00:02.000" end="00:00:03.000" region="band3"><br/><br/>LOWER</p>"#,
+        ]);
+    }
+
+    #[test]
+    fn windows_side_by_side_share_a_paragraph() {
+        let document = write("windows.ttml", &[caption(1.0, 2.0, &[(14, 20, "RIGHT"), (14, 0, "LEFT"), (12, 0, "A < B")])]);
+        
+        assert_eq!(matching_lines(&document, "<region").len(), 1);
+        assert_eq!(matching_lines(&document, "<p"), [
+            r#"<p begin="00:00:01.000" end="00:00:02.000" region="band4">A &lt; B<br/><br/>LEFT RIGHT</p>"#,
+        ]);
+    }
+}
@@ -0,0 +1,235 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use anyhow::{Result, anyhow};
+use log::info;
+use tokio::net::UdpSocket;
+use crate::ts_demux::TS_SYNC_BYTE;
+
+const RTP_VERSION: u8 = 2;
+const RTP_HEADER_SIZE: usize = 12;
+
+// A transport stream received over UDP, as udp://@239.1.1.1:5000 (multicast
+// group), udp://@:5000 (any local address) or udp://127.0.0.1:5000. A
+// ?localaddr=192.168.1.10 query picks the interface an IPv4 multicast group
+// is joined on. rtp:// URLs are accepted as well; datagrams are checked for
+// an RTP header either way, since encoders differ in whether they send one.
+#[derive(Debug, PartialEq)]
+pub struct UdpSource {
+    address: SocketAddr,
+    interface: Option<Ipv4Addr>,
+}
+
+impl UdpSource {
+    pub fn parse(input: &str) -> Result<Option<Self>> {
+        let Some(rest) = input.strip_prefix("udp://").or_else(|| input.strip_prefix("rtp://")) else {
+            return Ok(None);
+        };
+        
+        // The @ marks an address to listen on rather than send to; it is the
+        // only mode there is here, so it is optional
+        let rest = rest.strip_prefix('@').unwrap_or(rest);
+        let (rest, query) = match rest.split_once('?') {
+            Some((rest, query)) => (rest, Some(query)),
+            None => (rest, None),
+        };
+        let (host, port) = rest
+            .trim_end_matches('/')
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow!("{} has no port", input))?;
+        let port: u16 = port.parse().map_err(|_| anyhow!("Invalid port in {}", input))?;
+        
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let ip = if host.is_empty() {
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        } else {
+            host.parse().map_err(|_| anyhow!("Invalid address in {}, expected an IP address", input))?
+        };
+        
+        let mut interface = None;
+        for parameter in query.into_iter().flat_map(|query| query.split('&')) {
+            match parameter.split_once('=') {
+                Some(("localaddr", address)) => {
+                    let address: Ipv4Addr = address.parse()
+                        .map_err(|_| anyhow!("Invalid localaddr in {}, expected an IPv4 address", input))?;
+                    interface = Some(address);
+                }
+                _ => return Err(anyhow!("Unsupported option {} in {}", parameter, input)),
+            }
+        }
+        if interface.is_some() && !(ip.is_ipv4() && ip.is_multicast()) {
+            return Err(anyhow!("localaddr in {} only applies to IPv4 multicast groups", input));
+        }
+        
+        Ok(Some(Self {
+            address: SocketAddr::new(ip, port),
+            interface,
+        }))
+    }
+
+    // Binds to the port and joins the multicast group, if the address is one
+    pub async fn bind(&self) -> Result<UdpSocket> {
+        let ip = self.address.ip();
+        let bind_ip = match ip {
+            IpAddr::V4(_) if ip.is_multicast() => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) if ip.is_multicast() => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            _ => ip,
+        };
+        
+        let socket = UdpSocket::bind(SocketAddr::new(bind_ip, self.address.port()))
+            .await
+            .map_err(|e| anyhow!("Unable to listen on {}: {}", self.address, e))?;
+        
+        match ip {
+            IpAddr::V4(group) if group.is_multicast() => {
+                let interface = self.interface.unwrap_or(Ipv4Addr::UNSPECIFIED);
+                socket.join_multicast_v4(group, interface)
+                    .map_err(|e| anyhow!("Unable to join multicast group {} on {}: {}", group, interface, e))?;
+                info!("Joined multicast group {} on {}", group, interface);
+            }
+            IpAddr::V6(group) if group.is_multicast() => {
+                socket.join_multicast_v6(&group, 0)
+                    .map_err(|e| anyhow!("Unable to join multicast group {}: {}", group, e))?;
+                info!("Joined multicast group {}", group);
+            }
+            _ => {}
+        }
+        
+        Ok(socket)
+    }
+}
+
+impl fmt::Display for UdpSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "udp://@{}", self.address)?;
+        if let Some(interface) = self.interface {
+            write!(f, "?localaddr={}", interface)?;
+        }
+        Ok(())
+    }
+}
+
+// The transport stream packets of a datagram: either the whole datagram, or
+// the RTP payload (RFC 2250) after the fixed header, CSRC list and header
+// extension, without padding. None for datagrams that are neither.
+pub fn ts_payload(datagram: &[u8]) -> Option<&[u8]> {
+    if datagram.first() == Some(&TS_SYNC_BYTE) {
+        return Some(datagram);
+    }
+    if datagram.len() < RTP_HEADER_SIZE || datagram[0] >> 6 != RTP_VERSION {
+        return None;
+    }
+    
+    let csrc_count = (datagram[0] & 0x0F) as usize;
+    let mut header_size = RTP_HEADER_SIZE + csrc_count * 4;
+    if datagram[0] & 0x10 != 0 {
+        let extension = datagram.get(header_size + 2..header_size + 4)?;
+        header_size += 4 + u16::from_be_bytes([extension[0], extension[1]]) as usize * 4;
+    }
+    
+    let mut end = datagram.len();
+    if datagram[0] & 0x20 != 0 {
+        end = end.checked_sub(*datagram.last()? as usize)?;
+    }
+    
+    datagram.get(header_size..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ts_demux::TS_PACKET_SIZE;
+
+    fn source(address: &str, interface: Option<Ipv4Addr>) -> UdpSource {
+        UdpSource {
+            address: address.parse().unwrap(),
+            interface,
+        }
+    }
+
+    fn ts_packets(count: usize) -> Vec<u8> {
+        let mut packet = [0xFF; TS_PACKET_SIZE];
+        packet[0] = TS_SYNC_BYTE;
+        packet.repeat(count)
+    }
+
+    // RTP version 2 with the given flags and CSRC count in the first byte,
+    // MPEG-2 TS payload type 33
+    fn rtp_header(first_byte: u8) -> Vec<u8> {
+        vec![first_byte, 33, 0x12, 0x34, 0, 0, 0x0B, 0xB8, 0xDE, 0xAD, 0xBE, 0xEF]
+    }
+
+    #[test]
+    fn parses_multicast_and_unicast_urls() {
+        assert_eq!(UdpSource::parse("udp://@239.1.1.1:5000").unwrap(), Some(source("239.1.1.1:5000", None)));
+        assert_eq!(UdpSource::parse("rtp://239.1.1.1:5000/").unwrap(), Some(source("239.1.1.1:5000", None)));
+        assert_eq!(UdpSource::parse("udp://@:5000").unwrap(), Some(source("0.0.0.0:5000", None)));
+        assert_eq!(UdpSource::parse("udp://127.0.0.1:5000").unwrap(), Some(source("127.0.0.1:5000", None)));
+        assert_eq!(UdpSource::parse("udp://@[ff15::1]:5000").unwrap(), Some(source("[ff15::1]:5000", None)));
+        assert_eq!(UdpSource::parse("http://example.com/index.m3u8").unwrap(), None);
+    }
+
+    #[test]
+    fn parses_multicast_interface() {
+        let interface = Some(Ipv4Addr::new(192, 168, 1, 10));
+        let parsed = UdpSource::parse("udp://@239.1.1.1:5000?localaddr=192.168.1.10").unwrap().unwrap();
+        assert_eq!(parsed, source("239.1.1.1:5000", interface));
+        assert_eq!(parsed.to_string(), "udp://@239.1.1.1:5000?localaddr=192.168.1.10");
+        assert_eq!(
+            UdpSource::parse("rtp://@239.1.1.1:5000/?localaddr=192.168.1.10").unwrap(),
+            Some(source("239.1.1.1:5000", interface)),
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_urls() {
+        assert!(UdpSource::parse("udp://@239.1.1.1").is_err());
+        assert!(UdpSource::parse("udp://@239.1.1.1:port").is_err());
+        assert!(UdpSource::parse("udp://@example.com:5000").is_err());
+        assert!(UdpSource::parse("udp://@239.1.1.1:5000?localaddr=eth0").is_err());
+        assert!(UdpSource::parse("udp://@239.1.1.1:5000?ttl=4").is_err());
+        // An interface only makes sense for a multicast group
+        assert!(UdpSource::parse("udp://@:5000?localaddr=192.168.1.10").is_err());
+    }
+
+    #[test]
+    fn passes_raw_transport_stream_through() {
+        let datagram = ts_packets(7);
+        assert_eq!(ts_payload(&datagram), Some(datagram.as_slice()));
+    }
+
+    #[test]
+    fn strips_rtp_header() {
+        let packets = ts_packets(7);
+        let mut datagram = rtp_header(0x80);
+        datagram.extend_from_slice(&packets);
+        assert_eq!(ts_payload(&datagram), Some(packets.as_slice()));
+    }
+
+    #[test]
+    fn strips_csrc_list_extension_and_padding() {
+        let packets = ts_packets(2);
+        // Padding, extension and two CSRCs
+        let mut datagram = rtp_header(0x80 | 0x20 | 0x10 | 2);
+        datagram.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 2]);
+        // Extension header with a length of one 32-bit word
+        datagram.extend_from_slice(&[0xBE, 0xDE, 0x00, 0x01, 0x10, 0xAA, 0x00, 0x00]);
+        datagram.extend_from_slice(&packets);
+        // Three bytes of padding, the last one giving the count
+        datagram.extend_from_slice(&[0, 0, 3]);
+        assert_eq!(ts_payload(&datagram), Some(packets.as_slice()));
+    }
+
+    #[test]
+    fn rejects_other_datagrams() {
+        // Neither a sync byte nor RTP version 2
+        assert_eq!(ts_payload(&[0x00; TS_PACKET_SIZE]), None);
+        // Too short for an RTP header
+        assert_eq!(ts_payload(&rtp_header(0x80)[..8]), None);
+        // Header extension running past the end
+        assert_eq!(ts_payload(&rtp_header(0x80 | 0x10)), None);
+        // More padding than data
+        let mut datagram = rtp_header(0x80 | 0x20);
+        datagram.push(40);
+        assert_eq!(ts_payload(&datagram), None);
+    }
+}